@@ -0,0 +1,329 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, ItemImpl, LitStr};
+
+mod shv_node;
+
+/// Exposes a struct as a subtree of SHV nodes
+///
+/// Implements `shvclient::NodeTree` for the struct and generates a struct
+/// `<Name>Nodes` with a handle for every mounted field.
+///
+/// Every field becomes a child `PropertyNode` named by the field in
+/// camelCase. Field attributes:
+///  * `#[shv(rename = "name")]` - name of the child node
+///  * `#[shv(read_only)]` - do not provide `set`
+///  * `#[shv(access = Read, write_access = Write)]` - access levels
+///    of `get` and `set`
+///  * `#[shv(description = "...", type = "...")]` - metadata of `get`
+///    and `set`, the type defaults to the SHV name of the field type
+///    as in `#[shv_node]`
+///  * `#[shv(subtree)]` - the field is a `NodeTree` itself
+///
+/// The struct attribute `#[shv(methods)]` mounts also the subtree root
+/// node. Its methods are implemented on `<Name>Nodes` by `#[shv_node]`,
+/// so they can access the handles of the mounted nodes:
+///
+///```ignore
+/// #[derive(ShvNode)]
+/// #[shv(methods)]
+/// struct Device {
+///     temperature: f64,
+///     #[shv(read_only)]
+///     serial_number: String,
+/// }
+///
+/// #[shv_node]
+/// impl DeviceNodes {
+///     #[method(access = Command, description = "Reset the device")]
+///     async fn reset(&self) -> bool {
+///         self.temperature.set(0.0);
+///         true
+///     }
+/// }
+///
+/// let device_nodes = Device { temperature: 20.0, serial_number: "X1".into() }
+///     .mount_tree(&mut client, "device");
+///```
+#[proc_macro_derive(ShvNode, attributes(shv))]
+pub fn derive_shv_node(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_node_tree(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Exposes the methods of an impl block as a fixed node
+///
+/// Generates `into_node()` that creates a `ClientNode` dispatching the
+/// requests to the methods annotated by `#[method(...)]`. The methods
+/// take `&self`, may be async and can return any type implementing
+/// `shvclient::clientnode::IntoMethodResult`.
+///
+/// Method attributes:
+///  * `access = Write` - required access level
+///  * `name = "setX"` - name of the method, defaults to the function name
+///    in camelCase
+///  * `getter`, `setter` - method flags
+///  * `description = "..."`, `param = "..."`, `result = "..."` - metadata,
+///    the type names default to the SHV names of the types in the signature,
+///    e.g. `Option<i32>` is shown as `Int|Null`
+///  * `signals("xchng", ...)` - signals emitted by the method
+///
+/// A single argument is converted from the whole request param, several
+/// arguments from the fields of a Map param by name or of a List param by
+/// order. `Option<T>` arguments may be missing. Arguments of type
+/// `RpcMessage` and `ClientCommandSender` get the request and the sender,
+/// e.g. to respond later and return `DeferredResponse`.
+///
+///```ignore
+/// struct Counter { value: AtomicI32 }
+///
+/// #[shv_node]
+/// impl Counter {
+///     #[method(access = Read, getter)]
+///     fn get(&self) -> i32 {
+///         self.value.load(Ordering::Relaxed)
+///     }
+///
+///     #[method(access = Write, description = "Adds to the counter")]
+///     async fn add(&self, delta: i32, limit: Option<i32>) -> Result<i32, RpcError> {
+///         // ...
+///     }
+/// }
+///
+/// client.mount("counter", Counter { value: 0.into() }.into_node());
+///```
+#[proc_macro_attribute]
+pub fn shv_node(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(Span::call_site(), "#[shv_node] does not take any arguments")
+            .into_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(item as ItemImpl);
+    shv_node::expand(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct FieldAttrs {
+    name: Option<LitStr>,
+    read_only: bool,
+    subtree: bool,
+    access: Option<Ident>,
+    write_access: Option<Ident>,
+    description: Option<LitStr>,
+    type_name: Option<LitStr>,
+}
+
+fn expand_node_tree(input: DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(input.generics.span(), "ShvNode cannot be derived for generic structs"));
+    }
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(input.ident.span(), "ShvNode can be derived only for structs"));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new(input.ident.span(), "ShvNode can be derived only for structs with named fields"));
+    };
+
+    let vis = &input.vis;
+    let ident = &input.ident;
+    let nodes_ident = format_ident!("{}Nodes", ident);
+    let root_methods = parse_struct_attrs(&input)?;
+
+    let mut node_fields = Vec::new();
+    let mut node_inits = Vec::new();
+    for field in &fields.named {
+        let field_ident = field.ident.as_ref().expect("named field");
+        let ty = &field.ty;
+        let attrs = parse_field_attrs(field)?;
+        let node_name = attrs.name
+            .clone()
+            .unwrap_or_else(|| LitStr::new(&to_camel_case(&field_ident.to_string()), field_ident.span()));
+
+        if attrs.subtree {
+            if attrs.read_only || attrs.access.is_some() || attrs.write_access.is_some()
+                || attrs.description.is_some() || attrs.type_name.is_some()
+            {
+                return Err(syn::Error::new(field.span(), "A subtree field accepts only `rename`"));
+            }
+            node_fields.push(quote! {
+                pub #field_ident: <#ty as ::shvclient::NodeTree>::Nodes
+            });
+            let mount_tree = quote_spanned!(ty.span()=> <#ty as ::shvclient::NodeTree>::mount_tree);
+            node_inits.push(quote! {
+                #field_ident: #mount_tree(self.#field_ident, client, &child_path(#node_name))
+            });
+            continue;
+        }
+
+        let get_access = access_level(&attrs.access, "Read");
+        let set_access = access_level(&attrs.write_access, "Write");
+        let description = attrs.description.clone().unwrap_or_else(|| LitStr::new("", Span::call_site()));
+        let type_name = match &attrs.type_name {
+            Some(type_name) => quote!(#type_name),
+            None => shv_node::shv_type_name(&shv_node::rust_type_name(ty)),
+        };
+        let (constructor, set_method, methods_count) = if attrs.read_only {
+            (quote!(read_only), quote!(), 1usize)
+        } else {
+            let set_method = quote! {
+                ::shvclient::clientnode::MetaMethod {
+                    name: ::shvclient::clientnode::METH_SET,
+                    flags: ::shvclient::clientnode::Flag::IsSetter as u32,
+                    access: #set_access,
+                    param: #type_name,
+                    result: "",
+                    description: #description,
+                },
+            };
+            (quote!(new), set_method, 2usize)
+        };
+        node_fields.push(quote_spanned! {ty.span()=>
+            pub #field_ident: ::shvclient::PropertyNode<#ty>
+        });
+        let property_node = quote_spanned!(ty.span()=> ::shvclient::PropertyNode::<#ty>);
+        node_inits.push(quote! {
+            #field_ident: {
+                const METHODS: [::shvclient::clientnode::MetaMethod; #methods_count] = [
+                    ::shvclient::clientnode::MetaMethod {
+                        name: ::shvclient::clientnode::METH_GET,
+                        flags: ::shvclient::clientnode::Flag::IsGetter as u32,
+                        access: #get_access,
+                        param: "",
+                        result: #type_name,
+                        description: #description,
+                    },
+                    #set_method
+                ];
+                let property = #property_node::#constructor(self.#field_ident).with_methods(&METHODS);
+                client.mount_property(child_path(#node_name), &property);
+                property
+            }
+        });
+    }
+
+    let root_mount = match root_methods {
+        // `into_node()` is generated by `#[shv_node]` on the impl block of the nodes
+        Some(span) => quote_spanned! {span=>
+            client.mount(path, ::std::clone::Clone::clone(&nodes).into_node());
+        },
+        None => quote!(),
+    };
+
+    Ok(quote! {
+        #[derive(Clone)]
+        #vis struct #nodes_ident {
+            #(#node_fields,)*
+        }
+
+        impl ::shvclient::NodeTree for #ident {
+            type Nodes = #nodes_ident;
+
+            fn mount_tree<T: Send + Sync + 'static>(self, client: &mut ::shvclient::Client<T>, path: &str) -> Self::Nodes {
+                #[allow(unused)]
+                let child_path = |name: &str| if path.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{path}/{name}")
+                };
+                let nodes = #nodes_ident {
+                    #(#node_inits,)*
+                };
+                #root_mount
+                nodes
+            }
+        }
+    })
+}
+
+fn access_level(access: &Option<Ident>, default: &str) -> TokenStream2 {
+    let access = access.clone().unwrap_or_else(|| Ident::new(default, Span::call_site()));
+    quote_spanned!(access.span()=> ::shvclient::clientnode::AccessLevel::#access)
+}
+
+fn parse_field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs {
+        name: None,
+        read_only: false,
+        subtree: false,
+        access: None,
+        write_access: None,
+        description: None,
+        type_name: None,
+    };
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("shv")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                attrs.name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("read_only") {
+                attrs.read_only = true;
+            } else if meta.path.is_ident("subtree") {
+                attrs.subtree = true;
+            } else if meta.path.is_ident("access") {
+                attrs.access = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("write_access") {
+                attrs.write_access = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("description") {
+                attrs.description = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("type") {
+                attrs.type_name = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("unsupported shv field attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    if let (true, Some(write_access)) = (attrs.read_only, &attrs.write_access) {
+        return Err(syn::Error::new(write_access.span(), "`write_access` cannot be set on a read-only field"));
+    }
+    Ok(attrs)
+}
+
+// Returns the span of `#[shv(methods)]`, if present
+fn parse_struct_attrs(input: &DeriveInput) -> syn::Result<Option<Span>> {
+    let mut methods = None;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("shv")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("methods") {
+                return Err(meta.error("unsupported shv struct attribute, expected `methods`"));
+            }
+            methods = Some(meta.path.span());
+            Ok(())
+        })?;
+    }
+    Ok(methods)
+}
+
+pub(crate) fn to_camel_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut upper_next = false;
+    for c in name.trim_start_matches("r#").chars() {
+        if c == '_' {
+            upper_next = !result.is_empty();
+        } else if upper_next {
+            result.extend(c.to_uppercase());
+            upper_next = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camel_case_names() {
+        assert_eq!(to_camel_case("temperature"), "temperature");
+        assert_eq!(to_camel_case("serial_number"), "serialNumber");
+        assert_eq!(to_camel_case("_hidden_value"), "hiddenValue");
+        assert_eq!(to_camel_case("r#type"), "type");
+    }
+}
@@ -0,0 +1,457 @@
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{
+    FnArg, GenericArgument, Ident, ImplItem, ImplItemFn, ItemImpl, LitStr, Pat, PathArguments,
+    ReturnType, Type,
+};
+
+use crate::to_camel_case;
+
+struct MethodAttrs {
+    name: Option<LitStr>,
+    access: Option<Ident>,
+    getter: bool,
+    setter: bool,
+    description: Option<LitStr>,
+    param: Option<LitStr>,
+    result: Option<LitStr>,
+    signals: Vec<LitStr>,
+}
+
+enum ArgKind {
+    // The request message itself
+    Request,
+    // A sender for deferred responses or signals
+    CommandSender,
+    // A value converted from the request param
+    Param { name: Ident, ty: Box<Type>, optional: Option<Box<Type>> },
+}
+
+struct NodeMethod {
+    fn_ident: Ident,
+    name: LitStr,
+    attrs: MethodAttrs,
+    is_async: bool,
+    args: Vec<ArgKind>,
+    output: ReturnType,
+}
+
+pub(crate) fn expand(mut item: ItemImpl) -> syn::Result<TokenStream2> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(syn::Error::new(path.span(), "#[shv_node] is applicable only on inherent impl blocks"));
+    }
+
+    let mut methods: Vec<NodeMethod> = Vec::new();
+    for impl_item in item.items.iter_mut() {
+        let ImplItem::Fn(func) = impl_item else {
+            continue;
+        };
+        let Some(attr_index) = func.attrs.iter().position(|attr| attr.path().is_ident("method")) else {
+            continue;
+        };
+        let attr = func.attrs.remove(attr_index);
+        if let Some(dup) = func.attrs.iter().find(|attr| attr.path().is_ident("method")) {
+            return Err(syn::Error::new(dup.span(), "duplicate #[method] attribute"));
+        }
+        let attrs = parse_method_attrs(&attr)?;
+        let method = parse_method(func, attrs)?;
+        if ["dir", "ls"].contains(&method.name.value().as_str()) {
+            return Err(syn::Error::new(method.name.span(), "`dir` and `ls` are handled by the library"));
+        }
+        if let Some(dup) = methods.iter().find(|m| m.name.value() == method.name.value()) {
+            return Err(syn::Error::new(
+                method.fn_ident.span(),
+                format!("method `{}` is already defined by `{}`", method.name.value(), dup.fn_ident),
+            ));
+        }
+        methods.push(method);
+    }
+    if methods.is_empty() {
+        return Err(syn::Error::new(item.self_ty.span(), "#[shv_node] requires at least one #[method]"));
+    }
+
+    let meta_methods = methods.iter().map(meta_methods);
+    let methods_count = methods.len();
+    let method_names = methods.iter().map(|m| &m.name);
+    let signals = methods.iter().flat_map(|m| {
+        let name = &m.name;
+        m.attrs.signals.iter().map(move |signal| quote!((#name, #signal)))
+    });
+    let dispatch_arms = methods.iter().map(dispatch_arm);
+
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    let self_ty = &item.self_ty;
+
+    Ok(quote! {
+        #item
+
+        impl #impl_generics #self_ty #where_clause {
+            /// Creates a fixed node with the methods of this type
+            pub fn into_node<T: Send + Sync + 'static>(self) -> ::shvclient::clientnode::ClientNode<'static, T>
+            where
+                Self: Send + Sync + 'static,
+            {
+                #[allow(unused_imports)]
+                use ::shvclient::clientnode::RpcMessageMetaTags as _;
+
+                const METHODS: [::shvclient::clientnode::MetaMethod; #methods_count] = [#(#meta_methods)*];
+
+                let __node = ::std::sync::Arc::new(self);
+                let handler = ::shvclient::RequestHandler::stateless(move |__request, __client_cmd_tx| {
+                    let __node = __node.clone();
+                    async move {
+                        let result: ::std::option::Option<::std::result::Result<::shvclient::clientnode::RpcValue, ::shvclient::clientnode::RpcError>> = async {
+                            let __method = __request.method().unwrap_or_default();
+                            match __method {
+                                #(#dispatch_arms)*
+                                _ => ::std::option::Option::Some(::std::result::Result::Err(::shvclient::clientnode::RpcError::new(
+                                            ::shvclient::clientnode::RpcErrorCode::MethodNotFound,
+                                            format!("Invalid method: {:?}", __method)))),
+                            }
+                        }.await;
+                        if let ::std::option::Option::Some(result) = result {
+                            ::shvclient::clientnode::send_response(__request, __client_cmd_tx, result);
+                        }
+                    }
+                });
+                ::shvclient::clientnode::ClientNode::fixed(
+                    &METHODS,
+                    [::shvclient::Route::new([#(#method_names),*], handler)],
+                )
+                .with_signals(&[#(#signals),*])
+            }
+        }
+    })
+}
+
+fn parse_method_attrs(attr: &syn::Attribute) -> syn::Result<MethodAttrs> {
+    let mut attrs = MethodAttrs {
+        name: None,
+        access: None,
+        getter: false,
+        setter: false,
+        description: None,
+        param: None,
+        result: None,
+        signals: Vec::new(),
+    };
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("name") {
+            attrs.name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("access") {
+            attrs.access = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("getter") {
+            attrs.getter = true;
+        } else if meta.path.is_ident("setter") {
+            attrs.setter = true;
+        } else if meta.path.is_ident("description") {
+            attrs.description = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("param") {
+            attrs.param = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("result") {
+            attrs.result = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("signals") {
+            let content;
+            syn::parenthesized!(content in meta.input);
+            attrs.signals.extend(content.parse_terminated(|input| input.parse::<LitStr>(), syn::Token![,])?);
+        } else {
+            return Err(meta.error("unsupported method attribute"));
+        }
+        Ok(())
+    })?;
+    if attrs.access.is_none() {
+        return Err(syn::Error::new_spanned(attr, "missing `access` of the method"));
+    }
+    Ok(attrs)
+}
+
+fn parse_method(func: &ImplItemFn, attrs: MethodAttrs) -> syn::Result<NodeMethod> {
+    let sig = &func.sig;
+    match sig.inputs.first() {
+        Some(FnArg::Receiver(receiver)) if receiver.reference.is_some() && receiver.mutability.is_none() => { }
+        Some(arg) => return Err(syn::Error::new_spanned(arg, "a node method has to take `&self`")),
+        None => return Err(syn::Error::new(sig.ident.span(), "a node method has to take `&self`")),
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(sig.generics.span(), "a node method cannot be generic"));
+    }
+
+    let mut args = Vec::new();
+    for arg in sig.inputs.iter().skip(1) {
+        let FnArg::Typed(pat_type) = arg else {
+            return Err(syn::Error::new(arg.span(), "unexpected receiver"));
+        };
+        let Pat::Ident(pat_ident) = pat_type.pat.as_ref() else {
+            return Err(syn::Error::new(pat_type.pat.span(), "a node method argument has to be an identifier"));
+        };
+        let ty = pat_type.ty.as_ref();
+        let arg = match last_segment_ident(ty).as_deref() {
+            Some("RpcMessage") => ArgKind::Request,
+            Some("ClientCommandSender") => ArgKind::CommandSender,
+            _ => ArgKind::Param {
+                name: pat_ident.ident.clone(),
+                ty: Box::new(ty.clone()),
+                optional: option_inner(ty).cloned().map(Box::new),
+            },
+        };
+        args.push(arg);
+    }
+
+    let name = attrs.name
+        .clone()
+        .unwrap_or_else(|| LitStr::new(&to_camel_case(&sig.ident.to_string()), sig.ident.span()));
+
+    Ok(NodeMethod {
+        fn_ident: sig.ident.clone(),
+        name,
+        attrs,
+        is_async: sig.asyncness.is_some(),
+        args,
+        output: sig.output.clone(),
+    })
+}
+
+fn last_segment_ident(ty: &Type) -> Option<String> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    type_path.path.segments.last().map(|segment| segment.ident.to_string())
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    let segment = type_path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first() {
+        Some(GenericArgument::Type(inner)) if args.args.len() == 1 => Some(inner),
+        _ => None,
+    }
+}
+
+fn result_ok_type(ty: &Type) -> &Type {
+    let Type::Path(type_path) = ty else {
+        return ty;
+    };
+    let Some(segment) = type_path.path.segments.last() else {
+        return ty;
+    };
+    if segment.ident != "Result" {
+        return ty;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return ty;
+    };
+    match args.args.first() {
+        Some(GenericArgument::Type(ok)) => ok,
+        _ => ty,
+    }
+}
+
+// Formats a Rust type as written in the source, e.g. `Option<Vec<i32>>`
+pub(crate) fn rust_type_name(ty: &Type) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    let mut name = String::new();
+    for token in quote!(#ty).to_string().split_whitespace() {
+        let separate = name.ends_with(is_word) && token.starts_with(is_word);
+        if separate || name.ends_with(',') {
+            name.push(' ');
+        }
+        name.push_str(token);
+    }
+    name
+}
+
+// The SHV name of a Rust type in the method metadata, e.g. `[Int]|Null`,
+// resolved by the same mapping as in `fixed_node!`
+pub(crate) fn shv_type_name(rust_type: &str) -> TokenStream2 {
+    quote!(::shvclient::clientnode::shv_type_name(#rust_type).as_str())
+}
+
+fn param_args(method: &NodeMethod) -> impl Iterator<Item = (&Ident, &Type)> {
+    method.args.iter().filter_map(|arg| match arg {
+        ArgKind::Param { name, ty, .. } => Some((name, ty.as_ref())),
+        _ => None,
+    })
+}
+
+fn meta_methods(method: &NodeMethod) -> TokenStream2 {
+    let attrs = &method.attrs;
+    let name = &method.name;
+    let access = attrs.access.as_ref().expect("access is checked in parse_method_attrs");
+    let access = quote_spanned!(access.span()=> ::shvclient::clientnode::AccessLevel::#access);
+    let mut flags = Vec::new();
+    if attrs.getter {
+        flags.push(quote!(::shvclient::clientnode::Flag::IsGetter as u32));
+    }
+    if attrs.setter {
+        flags.push(quote!(::shvclient::clientnode::Flag::IsSetter as u32));
+    }
+    let flags = if flags.is_empty() {
+        quote!(::shvclient::clientnode::Flag::None as u32)
+    } else {
+        quote!(#(#flags)|*)
+    };
+    let empty = || LitStr::new("", Span::call_site());
+    let description = attrs.description.clone().unwrap_or_else(empty);
+
+    let param = if let Some(param) = &attrs.param {
+        quote!(#param)
+    } else {
+        let params = param_args(method).collect::<Vec<_>>();
+        match params.as_slice() {
+            [] => quote!(""),
+            [(_, ty)] => shv_type_name(&rust_type_name(ty)),
+            params => {
+                let fields = params
+                    .iter()
+                    .map(|(name, ty)| format!("{name}: {}", rust_type_name(ty)))
+                    .collect::<Vec<_>>();
+                shv_type_name(&format!("{{{}}}", fields.join(", ")))
+            }
+        }
+    };
+    let result = if let Some(result) = &attrs.result {
+        quote!(#result)
+    } else {
+        match &method.output {
+            ReturnType::Default => quote!(""),
+            ReturnType::Type(_, ty) => shv_type_name(&rust_type_name(result_ok_type(ty))),
+        }
+    };
+    quote! {
+        ::shvclient::clientnode::MetaMethod {
+            name: #name,
+            flags: #flags,
+            access: #access,
+            param: #param,
+            result: #result,
+            description: #description,
+        },
+    }
+}
+
+fn wrong_param(message: TokenStream2) -> TokenStream2 {
+    quote! {
+        return ::std::option::Option::Some(::std::result::Result::Err(::shvclient::clientnode::RpcError::new(
+                    ::shvclient::clientnode::RpcErrorCode::InvalidParam,
+                    #message)))
+    }
+}
+
+fn convert(value: TokenStream2, ty: &Type, on_error: impl FnOnce(TokenStream2) -> TokenStream2) -> TokenStream2 {
+    // Errors of unsupported param types point at the type in the method signature
+    let try_from = quote_spanned! {ty.span()=>
+        <#ty as ::std::convert::TryFrom<&::shvclient::clientnode::RpcValue>>::try_from(#value)
+    };
+    let err = on_error(quote!(err));
+    quote! {
+        match #try_from {
+            ::std::result::Result::Ok(value) => value,
+            ::std::result::Result::Err(err) => { #err; }
+        }
+    }
+}
+
+fn dispatch_arm(method: &NodeMethod) -> TokenStream2 {
+    let name = &method.name;
+    let params = param_args(method).count();
+    let mut bindings = Vec::new();
+    let mut call_args = Vec::new();
+    let mut param_index = 0usize;
+    for arg in &method.args {
+        match arg {
+            ArgKind::Request => call_args.push(quote!(__request.clone())),
+            ArgKind::CommandSender => call_args.push(quote!(__client_cmd_tx.clone())),
+            ArgKind::Param { name: arg_name, ty, optional } => {
+                let binding = if params == 1 {
+                    // A single param is converted from the whole request param
+                    let wrong = |err| wrong_param(quote!(format!("Wrong parameter for `{}`: {}", #name, #err)));
+                    match optional {
+                        Some(inner) => {
+                            let conversion = convert(quote!(value), inner, wrong);
+                            quote! {
+                                let #arg_name: #ty = match __request.param().filter(|value| !value.is_null()) {
+                                    ::std::option::Option::None => ::std::option::Option::None,
+                                    ::std::option::Option::Some(value) => ::std::option::Option::Some(#conversion),
+                                };
+                            }
+                        }
+                        None => {
+                            let conversion = convert(quote!(__request.param().unwrap_or_default()), ty, wrong);
+                            quote! {
+                                let #arg_name: #ty = #conversion;
+                            }
+                        }
+                    }
+                } else {
+                    let field = quote!(::shvclient::clientnode::param_field(__request_param, stringify!(#arg_name), #param_index));
+                    let wrong = |err| wrong_param(quote!(format!("Wrong value of field `{}` in parameter for `{}`: {}", stringify!(#arg_name), #name, #err)));
+                    match optional {
+                        Some(inner) => {
+                            let conversion = convert(quote!(value), inner, wrong);
+                            quote! {
+                                let #arg_name: #ty = match #field {
+                                    ::std::option::Option::None => ::std::option::Option::None,
+                                    ::std::option::Option::Some(value) => ::std::option::Option::Some(#conversion),
+                                };
+                            }
+                        }
+                        None => {
+                            let conversion = convert(quote!(value), ty, wrong);
+                            let missing = wrong_param(quote!(format!("Missing field `{}` in parameter for `{}`", stringify!(#arg_name), #name)));
+                            quote! {
+                                let #arg_name: #ty = match #field {
+                                    ::std::option::Option::None => { #missing; }
+                                    ::std::option::Option::Some(value) => #conversion,
+                                };
+                            }
+                        }
+                    }
+                };
+                param_index += 1;
+                bindings.push(binding);
+                call_args.push(quote!(#arg_name));
+            }
+        }
+    }
+
+    let check_param_shape = if params > 1 {
+        let wrong = wrong_param(quote!(format!("Wrong parameter for `{}`: expected Map or List", #name)));
+        quote! {
+            let __request_param = __request.param().filter(|value| !value.is_null());
+            if __request_param.is_some_and(|value| !value.is_map() && !value.is_list()) {
+                #wrong;
+            }
+        }
+    } else {
+        quote!()
+    };
+
+    let fn_ident = &method.fn_ident;
+    let call = if method.is_async {
+        quote!(__node.#fn_ident(#(#call_args),*).await)
+    } else {
+        quote!(__node.#fn_ident(#(#call_args),*))
+    };
+    let into_result = match &method.output {
+        ReturnType::Default => quote!(::shvclient::clientnode::IntoMethodResult::into_method_result(#call)),
+        ReturnType::Type(_, ty) => quote_spanned! {ty.span()=>
+            <#ty as ::shvclient::clientnode::IntoMethodResult>::into_method_result(#call)
+        },
+    };
+    quote! {
+        #name => {
+            #check_param_shape
+            #(#bindings)*
+            #into_result
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::clientnode::RequestAccess;
+use shvrpc::metamethod::AccessLevel;
+use shvproto::RpcValue;
+use std::path::Path;
+
+/// A rule requiring a minimal access level for the matching methods
+///
+/// `path` is a glob pattern where `*` matches any part of a single path
+/// segment and `**` matches any number of segments, e.g. `config/**`.
+/// `method` is a method name pattern, where `*` matches any characters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessRule {
+    pub path: String,
+    pub method: String,
+    pub access: i32,
+}
+
+impl AccessRule {
+    pub fn matches(&self, path: &str, method: &str) -> bool {
+        glob_matches(&self.path, path) && wildcard_matches(&self.method, method)
+    }
+}
+
+/// Access rules of a `Client` overriding the access levels of the mounted
+/// nodes' methods
+///
+/// A request is allowed only if its access level satisfies both the access
+/// level of the method and all the rules matching the request path and method.
+///
+/// The rules can be loaded from a CPON list of maps, e.g.:
+/// ```text
+/// [
+///   {"path": "config/**", "access": "srv"},
+///   {"path": "**", "method": "reset", "access": 48},
+/// ]
+/// ```
+/// `method` defaults to `*`, `access` is either a numeric access level or
+/// an access level name as used in the access strings.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccessRules(Vec<AccessRule>);
+
+impl AccessRules {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add_rule(mut self, path: impl Into<String>, method: impl Into<String>, access: AccessLevel) -> Self {
+        self.0.push(AccessRule {
+            path: path.into(),
+            method: method.into(),
+            access: access as i32,
+        });
+        self
+    }
+
+    pub fn rules(&self) -> &[AccessRule] {
+        &self.0
+    }
+
+    pub fn from_rpcvalue(value: &RpcValue) -> shvrpc::Result<Self> {
+        if !value.is_list() {
+            return Err(format!("Access rules must be a list, got: {}", value.to_cpon()).into());
+        }
+        let rules = value.as_list()
+            .iter()
+            .map(|rule| {
+                if !rule.is_map() {
+                    return Err(format!("Invalid access rule: {}", rule.to_cpon()));
+                }
+                let rule_map = rule.as_map();
+                let path = rule_map.get("path")
+                    .filter(|path| path.is_string())
+                    .ok_or_else(|| format!("Missing path in access rule: {}", rule.to_cpon()))?
+                    .as_str()
+                    .to_string();
+                let method = rule_map.get("method")
+                    .map_or("*", RpcValue::as_str)
+                    .to_string();
+                let access = match rule_map.get("access") {
+                    Some(access) if access.is_string() => RequestAccess::parse(access.as_str()).level(),
+                    Some(access) if access.is_int() => Some(access.as_i32()),
+                    _ => None,
+                }
+                .ok_or_else(|| format!("Missing or invalid access in access rule: {}", rule.to_cpon()))?;
+                Ok(AccessRule { path, method, access })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self(rules))
+    }
+
+    pub fn from_cpon(cpon: &str) -> shvrpc::Result<Self> {
+        let value = RpcValue::from_cpon(cpon).map_err(|err| format!("Cannot parse access rules: {err}"))?;
+        Self::from_rpcvalue(&value)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> shvrpc::Result<Self> {
+        Self::from_cpon(&std::fs::read_to_string(path)?)
+    }
+
+    /// Returns the highest access level required by the rules matching
+    /// the path and method, or `None` if no rule matches.
+    pub fn required_access(&self, path: &str, method: &str) -> Option<i32> {
+        self.0
+            .iter()
+            .filter(|rule| rule.matches(path, method))
+            .map(|rule| rule.access)
+            .max()
+    }
+
+    /// Checks whether the rules matching the path and method allow
+    /// a request of the access level
+    pub(crate) fn allows(&self, path: &str, method: &str, level: Option<i32>) -> bool {
+        match self.required_access(path, method) {
+            None => true,
+            Some(required) => level.is_some_and(|level| level >= required),
+        }
+    }
+}
+
+/// Matches a path against a glob pattern, see `AccessRule`
+pub(crate) fn glob_matches(pattern: &str, path: &str) -> bool {
+    path_matches(&split_path(pattern), &split_path(path))
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    if path.is_empty() {
+        Vec::new()
+    } else {
+        path.split('/').collect()
+    }
+}
+
+fn path_matches(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => path_matches(rest, path) || (!path.is_empty() && path_matches(pattern, &path[1..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((path_segment, path_rest)) => wildcard_matches(segment, path_segment) && path_matches(rest, path_rest),
+            None => false,
+        },
+    }
+}
+
+fn wildcard_matches(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => {
+            let Some(value) = value.strip_prefix(prefix) else {
+                return false;
+            };
+            value.char_indices()
+                .map(|(i, _)| i)
+                .chain([value.len()])
+                .any(|i| wildcard_matches(rest, &value[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_paths() {
+        let rule = |path: &str, method: &str| AccessRule { path: path.into(), method: method.into(), access: 0 };
+        assert!(rule("config/**", "*").matches("config", "get"));
+        assert!(rule("config/**", "*").matches("config/a/b", "set"));
+        assert!(!rule("config/**", "*").matches("configuration", "get"));
+        assert!(rule("**", "*").matches("", "ls"));
+        assert!(rule("*/status", "get").matches("dev1/status", "get"));
+        assert!(!rule("*/status", "get").matches("dev1/status", "set"));
+        assert!(!rule("*/status", "get").matches("a/dev1/status", "get"));
+        assert!(rule("**/temp*", "set*").matches("a/b/temperature", "setLimit"));
+        assert!(!rule("**/temp*", "set*").matches("a/b/temperature", "get"));
+    }
+
+    #[test]
+    fn load_rules() {
+        let rules = AccessRules::from_cpon(r#"[
+            {"path": "config/**", "access": "srv"},
+            {"path": "**", "method": "reset", "access": 48},
+        ]"#).unwrap();
+        assert_eq!(rules.rules().len(), 2);
+        assert_eq!(rules.required_access("config/net", "set"), Some(AccessLevel::Service as i32));
+        assert_eq!(rules.required_access("config", "reset"), Some(AccessLevel::SuperService as i32));
+        assert_eq!(rules.required_access("status", "get"), None);
+
+        assert!(AccessRules::from_cpon(r#"[{"path": "a"}]"#).is_err());
+        assert!(AccessRules::from_cpon(r#"[{"access": "rd"}]"#).is_err());
+        assert!(AccessRules::from_cpon(r#"{}"#).is_err());
+    }
+}
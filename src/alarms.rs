@@ -0,0 +1,251 @@
+use crate::client::{ClientCommandSender, RequestHandler};
+use crate::clientnode::{send_response, ClientNode, Route, METH_GET, SIG_CHNG};
+use crate::signalemitter::SignalEmitter;
+use shvrpc::metamethod::{AccessLevel, Flag, MetaMethod};
+use shvrpc::rpcmessage::{RpcError, RpcErrorCode};
+use shvrpc::{RpcMessage, RpcMessageMetaTags};
+use shvproto::{rpcvalue, DateTime, RpcValue};
+use std::sync::{Arc, Mutex};
+
+pub const METH_ACK: &str = "ack";
+
+const ALARMS_METHODS: [MetaMethod; 2] = [
+    MetaMethod {
+        name: METH_GET,
+        flags: Flag::IsGetter as u32,
+        access: AccessLevel::Read,
+        param: "",
+        result: "[Alarm]",
+        description: "Active and unacknowledged alarms",
+    },
+    MetaMethod {
+        name: METH_ACK,
+        flags: Flag::None as u32,
+        access: AccessLevel::Write,
+        param: "String",
+        result: "Bool",
+        description: "Acknowledges an alarm by its ID",
+    },
+];
+
+const ALARMS_SIGNALS: [(&str, &str); 1] = [(METH_GET, SIG_CHNG)];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlarmSeverity {
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl AlarmSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlarmSeverity::Info => "info",
+            AlarmSeverity::Warning => "warning",
+            AlarmSeverity::Error => "error",
+            AlarmSeverity::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alarm {
+    pub id: String,
+    pub severity: AlarmSeverity,
+    pub message: String,
+    pub timestamp: DateTime,
+    pub active: bool,
+    pub acknowledged: bool,
+}
+
+impl From<&Alarm> for RpcValue {
+    fn from(alarm: &Alarm) -> Self {
+        let mut map = rpcvalue::Map::new();
+        map.insert("id".into(), alarm.id.as_str().into());
+        map.insert("severity".into(), alarm.severity.as_str().into());
+        map.insert("message".into(), alarm.message.as_str().into());
+        map.insert("timestamp".into(), alarm.timestamp.into());
+        map.insert("active".into(), alarm.active.into());
+        map.insert("acknowledged".into(), alarm.acknowledged.into());
+        map.into()
+    }
+}
+
+struct AlarmsInner {
+    alarms: Mutex<Vec<Alarm>>,
+    emitter: SignalEmitter,
+}
+
+/// A set of alarms of a device
+///
+/// An alarm stays in the set until it is both inactive and acknowledged.
+/// Every change is announced by the `chng` signal with the whole set and
+/// the set is announced again whenever the client connects to a broker.
+/// The handle is cheap to clone, mount it by `Client::mount_alarms()`.
+#[derive(Clone)]
+pub struct Alarms {
+    inner: Arc<AlarmsInner>,
+}
+
+impl Default for Alarms {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Alarms {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(AlarmsInner {
+                alarms: Mutex::new(Vec::new()),
+                emitter: SignalEmitter::default(),
+            }),
+        }
+    }
+
+    pub fn get(&self) -> Vec<Alarm> {
+        self.inner.alarms.lock().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    pub fn active(&self) -> Vec<Alarm> {
+        self.get().into_iter().filter(|alarm| alarm.active).collect()
+    }
+
+    /// Raises an alarm. An alarm with the same ID gets activated again
+    /// and needs a new acknowledgement.
+    pub fn raise(&self, id: impl Into<String>, severity: AlarmSeverity, message: impl Into<String>) {
+        let alarm = Alarm {
+            id: id.into(),
+            severity,
+            message: message.into(),
+            timestamp: DateTime::now(),
+            active: true,
+            acknowledged: false,
+        };
+        let mut alarms = self.inner.alarms.lock().unwrap_or_else(|err| err.into_inner());
+        match alarms.iter_mut().find(|a| a.id == alarm.id) {
+            Some(current) => *current = alarm,
+            None => alarms.push(alarm),
+        }
+        self.emit_chng(&alarms);
+    }
+
+    /// Deactivates an alarm. Returns `false` if the alarm is not active.
+    pub fn clear(&self, id: &str) -> bool {
+        self.modify(id, |alarm| {
+            if !alarm.active {
+                return false;
+            }
+            alarm.active = false;
+            alarm.timestamp = DateTime::now();
+            true
+        })
+    }
+
+    /// Acknowledges an alarm. Returns `false` if the alarm does not exist
+    /// or has already been acknowledged.
+    pub fn acknowledge(&self, id: &str) -> bool {
+        self.modify(id, |alarm| {
+            if alarm.acknowledged {
+                return false;
+            }
+            alarm.acknowledged = true;
+            true
+        })
+    }
+
+    fn modify(&self, id: &str, f: impl FnOnce(&mut Alarm) -> bool) -> bool {
+        let mut alarms = self.inner.alarms.lock().unwrap_or_else(|err| err.into_inner());
+        let Some(alarm) = alarms.iter_mut().find(|a| a.id == id) else {
+            return false;
+        };
+        if !f(alarm) {
+            return false;
+        }
+        alarms.retain(|a| a.active || !a.acknowledged);
+        self.emit_chng(&alarms);
+        true
+    }
+
+    /// Sends the current set of alarms by the `chng` signal
+    pub fn announce(&self) {
+        let alarms = self.inner.alarms.lock().unwrap_or_else(|err| err.into_inner());
+        self.emit_chng(&alarms);
+    }
+
+    fn emit_chng(&self, alarms: &[Alarm]) {
+        self.inner.emitter.emit(SIG_CHNG, alarms_to_rpcvalue(alarms));
+    }
+
+    pub(crate) fn attach(&self, path: &str, client_cmd_tx: ClientCommandSender) {
+        self.inner.emitter.attach(path, client_cmd_tx);
+    }
+
+    fn process_request(&self, request: RpcMessage, client_cmd_tx: ClientCommandSender) {
+        let result = match request.method() {
+            Some(METH_GET) => Ok(alarms_to_rpcvalue(&self.get())),
+            Some(METH_ACK) => match request.param() {
+                Some(id) if id.is_string() => Ok(self.acknowledge(id.as_str()).into()),
+                _ => Err(RpcError::new(
+                        RpcErrorCode::InvalidParam,
+                        format!("Alarm ID expected as a parameter of `{METH_ACK}`"))),
+            },
+            method => Err(RpcError::new(
+                    RpcErrorCode::MethodNotFound,
+                    format!("Invalid method: {method:?}"))),
+        };
+        send_response(request, client_cmd_tx, result);
+    }
+
+    pub(crate) fn client_node<T: Send + Sync + 'static>(&self) -> ClientNode<'static, T> {
+        let alarms = self.clone();
+        let handler = RequestHandler::stateless(move |request, client_cmd_tx| {
+            alarms.process_request(request, client_cmd_tx);
+            async { }
+        });
+        ClientNode::fixed(&ALARMS_METHODS, [Route::new([METH_GET, METH_ACK], handler)])
+            .with_signals(&ALARMS_SIGNALS)
+    }
+}
+
+fn alarms_to_rpcvalue(alarms: &[Alarm]) -> RpcValue {
+    alarms.iter().map(RpcValue::from).collect::<rpcvalue::List>().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signalemitter::test_utils::{command_channel, expect_signal};
+
+    #[test]
+    fn alarm_lifecycle() {
+        let (client_cmd_tx, mut receiver) = command_channel();
+        let alarms = Alarms::new();
+        alarms.attach("test/alarms", client_cmd_tx);
+        alarms.raise("temp", AlarmSeverity::Warning, "High temperature");
+        assert_eq!(expect_signal(&mut receiver, "test/alarms", SIG_CHNG).as_list().len(), 1);
+        assert!(alarms.acknowledge("temp"));
+        assert_eq!(expect_signal(&mut receiver, "test/alarms", SIG_CHNG).as_list().len(), 1);
+        assert!(!alarms.acknowledge("temp"));
+        assert!(alarms.clear("temp"));
+        assert_eq!(expect_signal(&mut receiver, "test/alarms", SIG_CHNG).as_list().len(), 0);
+        assert!(alarms.get().is_empty());
+        assert!(receiver.try_next().is_err());
+    }
+
+    #[test]
+    fn cleared_alarm_waits_for_ack() {
+        let (client_cmd_tx, mut receiver) = command_channel();
+        let alarms = Alarms::new();
+        alarms.attach("test/alarms", client_cmd_tx);
+        alarms.raise("door", AlarmSeverity::Error, "Door open");
+        assert_eq!(expect_signal(&mut receiver, "test/alarms", SIG_CHNG).as_list().len(), 1);
+        assert!(alarms.clear("door"));
+        assert_eq!(expect_signal(&mut receiver, "test/alarms", SIG_CHNG).as_list().len(), 1);
+        assert!(alarms.active().is_empty());
+        assert!(!alarms.get()[0].acknowledged);
+        assert!(alarms.acknowledge("door"));
+        assert_eq!(expect_signal(&mut receiver, "test/alarms", SIG_CHNG).as_list().len(), 0);
+    }
+}
@@ -1,25 +1,39 @@
 
-use crate::clientnode::{ConstantNode, METH_PING};
+use crate::client::{AppState, ClientCommandSender, RequestContext};
+use crate::signalemitter::SignalEmitter;
+use crate::clientnode::{ClientNode, ConstantNode, ShvNode, METH_DIR, METH_LS, METH_PING};
+use futures::future::BoxFuture;
+use futures::Future;
 use shvrpc::metamethod::{AccessLevel, Flag, MetaMethod};
-use shvrpc::{RpcMessageMetaTags, RpcMessage, rpcmessage::RpcError};
-use shvproto::RpcValue;
+use shvrpc::{RpcMessageMetaTags, RpcMessage, rpcmessage::{RpcError, RpcErrorCode}};
+use shvproto::{DateTime, RpcValue};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 const METH_SHV_VERSION_MAJOR: &str = "shvVersionMajor";
 const METH_SHV_VERSION_MINOR: &str = "shvVersionMinor";
 const METH_NAME: &str = "name";
 const METH_VERSION: &str = "version";
 const METH_SERIAL_NUMBER: &str = "serialNumber";
+const METH_DATE: &str = "date";
+const METH_UPTIME: &str = "uptime";
 
 const SHV_VERSION_MAJOR: i32 = 3;
 const SHV_VERSION_MINOR: i32 = 0;
 
-const DOT_APP_METHODS: [MetaMethod; 4] = [
+// The uptime of the `.app` and `.device` nodes is measured from the creation
+// of their client, which sets the start on the nodes it mounts
+fn uptime(start: Instant) -> RpcValue {
+    (start.elapsed().as_secs() as i64).into()
+}
+
+const DOT_APP_METHODS: [MetaMethod; 7] = [
     MetaMethod {
         name: METH_SHV_VERSION_MAJOR,
         flags: Flag::IsGetter as u32,
         access: AccessLevel::Browse,
         param: "",
-        result: "",
+        result: "Int",
         description: "",
     },
     MetaMethod {
@@ -27,7 +41,7 @@ const DOT_APP_METHODS: [MetaMethod; 4] = [
         flags: Flag::IsGetter as u32,
         access: AccessLevel::Browse,
         param: "",
-        result: "",
+        result: "Int",
         description: "",
     },
     MetaMethod {
@@ -35,9 +49,17 @@ const DOT_APP_METHODS: [MetaMethod; 4] = [
         flags: Flag::IsGetter as u32,
         access: AccessLevel::Browse,
         param: "",
-        result: "",
+        result: "String",
         description: "",
     },
+    MetaMethod {
+        name: METH_VERSION,
+        flags: Flag::IsGetter as u32,
+        access: AccessLevel::Browse,
+        param: "",
+        result: "String|Null",
+        description: "Version of the application, null if not set",
+    },
     MetaMethod {
         name: METH_PING,
         flags: Flag::None as u32,
@@ -46,27 +68,89 @@ const DOT_APP_METHODS: [MetaMethod; 4] = [
         result: "",
         description: "",
     },
+    MetaMethod {
+        name: METH_DATE,
+        flags: Flag::IsGetter as u32,
+        access: AccessLevel::Browse,
+        param: "",
+        result: "DateTime",
+        description: "Current date and time of the application",
+    },
+    MetaMethod {
+        name: METH_UPTIME,
+        flags: Flag::IsGetter as u32,
+        access: AccessLevel::Read,
+        param: "",
+        result: "Int",
+        description: "Seconds since the client was created",
+    },
 ];
 
+type CustomMethodHandler = Box<dyn Fn(&RpcMessage) -> Result<RpcValue, RpcError> + Send + Sync>;
+
+/// The `.app` node of the client
+///
+/// Implements the standard SHV 3 `.app` methods. `dir` and `ls`, including
+/// their SHV 3 forms asking for a single method or child, are handled
+/// by the library for all the nodes, so the node answers only its own
+/// methods and the custom ones added by `with_method()`.
 pub struct DotAppNode {
     app_name: String,
+    version: Option<String>,
     shv_version_major: i32,
     shv_version_minor: i32,
+    custom_methods: Vec<(&'static MetaMethod, CustomMethodHandler)>,
+    start: Instant,
 }
 
 impl DotAppNode {
     pub fn new(app_name: impl Into<String>) -> Self {
         Self {
             app_name: app_name.into(),
+            version: None,
             shv_version_major: SHV_VERSION_MAJOR,
             shv_version_minor: SHV_VERSION_MINOR,
+            custom_methods: Vec::new(),
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn with_start(mut self, start: Instant) -> Self {
+        self.start = start;
+        self
+    }
+
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    pub fn with_shv_version(mut self, major: i32, minor: i32) -> Self {
+        self.shv_version_major = major;
+        self.shv_version_minor = minor;
+        self
+    }
+
+    /// Adds a custom method to the node. Panics if a method with the same
+    /// name already exists.
+    pub fn with_method<F>(mut self, method: &'static MetaMethod, handler: F) -> Self
+    where
+        F: Fn(&RpcMessage) -> Result<RpcValue, RpcError> + Send + Sync + 'static,
+    {
+        if self.methods().iter().any(|mm| mm.name == method.name) || [METH_DIR, METH_LS].contains(&method.name) {
+            panic!("Duplicate method '{}' in the .app node", method.name);
         }
+        self.custom_methods.push((method, Box::new(handler)));
+        self
     }
 }
 
 impl ConstantNode for DotAppNode {
     fn methods(&self) -> Vec<&MetaMethod> {
-        DOT_APP_METHODS.iter().collect()
+        DOT_APP_METHODS
+            .iter()
+            .chain(self.custom_methods.iter().map(|(mm, _)| *mm))
+            .collect()
     }
 
     fn process_request(&self, request: &RpcMessage) -> Option<Result<RpcValue, RpcError>> {
@@ -74,19 +158,31 @@ impl ConstantNode for DotAppNode {
             Some(METH_SHV_VERSION_MAJOR) => Some(self.shv_version_major.into()),
             Some(METH_SHV_VERSION_MINOR) => Some(self.shv_version_minor.into()),
             Some(METH_NAME) => Some(RpcValue::from(&self.app_name)),
+            Some(METH_VERSION) => Some(self.version.as_ref().map_or_else(RpcValue::null, RpcValue::from)),
             Some(METH_PING) => Some(().into()),
-            _ => None,
+            Some(METH_DATE) => Some(DateTime::now().into()),
+            Some(METH_UPTIME) => Some(uptime(self.start)),
+            Some(method) => return self.custom_methods
+                .iter()
+                .find(|(mm, _)| mm.name == method)
+                .map(|(_, handler)| handler(request)),
+            None => None,
         }.map(Ok)
     }
 }
 
-const DOT_DEVICE_METHODS: [MetaMethod; 3] = [
+const METH_RESET: &str = "reset";
+const METH_ALERTS: &str = "alerts";
+// The signal of `.device:alerts` as named by the SHV device standard
+const SIG_ALERTSMOD: &str = "alertsmod";
+
+const DOT_DEVICE_METHODS: [MetaMethod; 5] = [
     MetaMethod {
         name: METH_NAME,
         flags: Flag::IsGetter as u32,
         access: AccessLevel::Browse,
         param: "",
-        result: "",
+        result: "String",
         description: "",
     },
     MetaMethod {
@@ -94,7 +190,7 @@ const DOT_DEVICE_METHODS: [MetaMethod; 3] = [
         flags: Flag::IsGetter as u32,
         access: AccessLevel::Browse,
         param: "",
-        result: "",
+        result: "String",
         description: "",
     },
     MetaMethod {
@@ -102,15 +198,160 @@ const DOT_DEVICE_METHODS: [MetaMethod; 3] = [
         flags: Flag::IsGetter as u32,
         access: AccessLevel::Browse,
         param: "",
-        result: "",
+        result: "String|Null",
         description: "",
     },
+    MetaMethod {
+        name: METH_UPTIME,
+        flags: Flag::IsGetter as u32,
+        access: AccessLevel::Read,
+        param: "",
+        result: "Int",
+        description: "Seconds since the client was created",
+    },
+    MetaMethod {
+        name: METH_ALERTS,
+        flags: Flag::IsGetter as u32,
+        access: AccessLevel::Read,
+        param: "",
+        result: "[Alert]",
+        description: "Active alerts of the device",
+    },
 ];
 
+const DOT_DEVICE_SIGNALS: [(&str, &str); 1] = [(METH_ALERTS, SIG_ALERTSMOD)];
+
+const METH_RESET_META: MetaMethod = MetaMethod {
+    name: METH_RESET,
+    flags: Flag::None as u32,
+    access: AccessLevel::Service,
+    param: "",
+    result: "",
+    description: "Resets the device",
+};
+
+/// An alert of a device
+///
+/// `level` is in range 0..=63, where levels up to 20 are notices,
+/// up to 42 warnings and the rest are errors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub id: String,
+    pub level: i32,
+    pub info: Option<RpcValue>,
+    pub date: DateTime,
+}
+
+impl Alert {
+    pub fn new(id: impl Into<String>, level: i32) -> Self {
+        Self {
+            id: id.into(),
+            level,
+            info: None,
+            date: DateTime::now(),
+        }
+    }
+
+    pub fn with_info(mut self, info: impl Into<RpcValue>) -> Self {
+        self.info = Some(info.into());
+        self
+    }
+}
+
+impl From<&Alert> for RpcValue {
+    fn from(alert: &Alert) -> Self {
+        let mut imap = shvproto::rpcvalue::IMap::new();
+        imap.insert(0, alert.date.into());
+        imap.insert(1, alert.level.into());
+        imap.insert(2, alert.id.as_str().into());
+        if let Some(info) = &alert.info {
+            imap.insert(3, info.clone());
+        }
+        imap.into()
+    }
+}
+
+struct AlertsInner {
+    alerts: Mutex<Vec<Alert>>,
+    emitter: SignalEmitter,
+}
+
+/// A handle to the alerts of a `DotDeviceNode`
+///
+/// Any change of the alerts is announced by the `alertsmod` signal
+/// of the `alerts` method, as defined by the SHV device standard.
+#[derive(Clone)]
+pub struct DeviceAlerts {
+    inner: Arc<AlertsInner>,
+}
+
+impl DeviceAlerts {
+    fn new() -> Self {
+        Self {
+            inner: Arc::new(AlertsInner {
+                alerts: Mutex::new(Vec::new()),
+                emitter: SignalEmitter::default(),
+            }),
+        }
+    }
+
+    pub fn get(&self) -> Vec<Alert> {
+        self.inner.alerts.lock().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    /// Raises an alert, replacing any active alert with the same ID
+    pub fn raise(&self, alert: Alert) {
+        let mut alerts = self.inner.alerts.lock().unwrap_or_else(|err| err.into_inner());
+        match alerts.iter_mut().find(|a| a.id == alert.id) {
+            Some(active) => *active = alert,
+            None => alerts.push(alert),
+        }
+        self.emit_alertsmod(&alerts);
+    }
+
+    /// Clears an alert. Returns `true` if the alert has been active.
+    pub fn clear(&self, id: &str) -> bool {
+        let mut alerts = self.inner.alerts.lock().unwrap_or_else(|err| err.into_inner());
+        let count = alerts.len();
+        alerts.retain(|a| a.id != id);
+        if alerts.len() == count {
+            return false;
+        }
+        self.emit_alertsmod(&alerts);
+        true
+    }
+
+    pub fn clear_all(&self) {
+        let mut alerts = self.inner.alerts.lock().unwrap_or_else(|err| err.into_inner());
+        if alerts.is_empty() {
+            return;
+        }
+        alerts.clear();
+        self.emit_alertsmod(&alerts);
+    }
+
+    fn emit_alertsmod(&self, alerts: &[Alert]) {
+        self.inner.emitter.emit(SIG_ALERTSMOD, alerts_to_rpcvalue(alerts));
+    }
+
+    pub(crate) fn attach(&self, path: &str, client_cmd_tx: ClientCommandSender) {
+        self.inner.emitter.attach(path, client_cmd_tx);
+    }
+}
+
+fn alerts_to_rpcvalue(alerts: &[Alert]) -> RpcValue {
+    alerts.iter().map(RpcValue::from).collect::<shvproto::rpcvalue::List>().into()
+}
+
+type ResetHandler = Box<dyn Fn() -> BoxFuture<'static, Result<(), RpcError>> + Send + Sync>;
+
 pub struct DotDeviceNode {
     device_name: String,
     version: String,
     serial_number: Option<String>,
+    alerts: DeviceAlerts,
+    reset: Option<ResetHandler>,
+    start: Instant,
 }
 
 impl DotDeviceNode {
@@ -119,25 +360,141 @@ impl DotDeviceNode {
             device_name: device_name.into(),
             version: version.into(),
             serial_number: serial_number.into(),
+            alerts: DeviceAlerts::new(),
+            reset: None,
+            start: Instant::now(),
         }
     }
-}
 
-impl ConstantNode for DotDeviceNode {
-    fn methods(&self) -> Vec<&MetaMethod> {
-        DOT_DEVICE_METHODS.iter().collect()
+    pub(crate) fn with_start(mut self, start: Instant) -> Self {
+        self.start = start;
+        self
     }
 
-    fn process_request(&self, request: &RpcMessage) -> Option<Result<RpcValue, RpcError>> {
-        match request.method() {
-            Some(METH_NAME) => Some(RpcValue::from(&self.device_name)),
-            Some(METH_VERSION) => Some(RpcValue::from(&self.version)),
-            Some(METH_SERIAL_NUMBER) => match &self.serial_number {
+    /// Enables the `reset` method, which calls `handler`
+    pub fn with_reset<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), RpcError>> + Send + 'static,
+    {
+        self.reset = Some(Box::new(move || Box::pin(handler())));
+        self
+    }
+
+    /// Returns a handle to raise and clear the alerts of the device
+    pub fn alerts(&self) -> DeviceAlerts {
+        self.alerts.clone()
+    }
+
+    pub(crate) fn into_client_node<T: Send + Sync + 'static>(self) -> ClientNode<'static, T> {
+        ClientNode::new(self).with_signals(&DOT_DEVICE_SIGNALS)
+    }
+
+    fn process_getter(&self, method: &str) -> Option<RpcValue> {
+        match method {
+            METH_NAME => Some(RpcValue::from(&self.device_name)),
+            METH_VERSION => Some(RpcValue::from(&self.version)),
+            METH_SERIAL_NUMBER => match &self.serial_number {
                 None => Some(RpcValue::null()),
                 Some(sn) => Some(RpcValue::from(sn)),
             },
+            METH_UPTIME => Some(uptime(self.start)),
+            METH_ALERTS => Some(alerts_to_rpcvalue(&self.alerts.get())),
             _ => None,
-        }.map(Ok)
+        }
     }
 }
 
+impl<T> ShvNode<T> for DotDeviceNode {
+    fn methods<'a>(&'a self, path: &'a str, _app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<&'a MetaMethod>>> {
+        let methods = path.is_empty().then(|| {
+            DOT_DEVICE_METHODS
+                .iter()
+                .chain(self.reset.as_ref().map(|_| &METH_RESET_META))
+                .collect()
+        });
+        Box::pin(async move { methods })
+    }
+
+    fn children<'a>(&'a self, _path: &'a str, _app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<String>>> {
+        Box::pin(async { Some(Vec::new()) })
+    }
+
+    fn process_request<'a>(&'a self, ctx: RequestContext<T>) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let method = ctx.request().method().unwrap_or_default().to_owned();
+            if let Some(result) = self.process_getter(&method) {
+                ctx.respond(result);
+                return;
+            }
+            match (method.as_str(), &self.reset) {
+                (METH_RESET, Some(reset)) => {
+                    let result = reset().await.map(|_| RpcValue::null());
+                    ctx.respond_result(result);
+                }
+                _ => ctx.respond_error(RpcError::new(
+                        RpcErrorCode::MethodNotFound,
+                        format!("Invalid method: {method}"))),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signalemitter::test_utils::{command_channel, expect_signal};
+
+    fn call(node: &DotAppNode, method: &str) -> Option<Result<RpcValue, RpcError>> {
+        node.process_request(&RpcMessage::new_request(".app", method, None))
+    }
+
+    #[test]
+    fn dot_app_custom_methods() {
+        const METH_FAIL: MetaMethod = MetaMethod {
+            name: "fail",
+            flags: Flag::None as u32,
+            access: AccessLevel::Write,
+            param: "",
+            result: "",
+            description: "",
+        };
+        let node = DotAppNode::new("test")
+            .with_version("1.2.3")
+            .with_method(&METH_FAIL, |_| Err(RpcError::new(RpcErrorCode::MethodCallException, "failed")));
+        assert_eq!(call(&node, METH_VERSION).unwrap().unwrap().as_str(), "1.2.3");
+        assert_eq!(node.methods().len(), DOT_APP_METHODS.len() + 1);
+        assert_eq!(call(&node, "fail").unwrap().unwrap_err().code, RpcErrorCode::MethodCallException);
+        assert!(call(&node, "foo").is_none());
+        assert!(call(&node, METH_UPTIME).unwrap().unwrap().is_int());
+        let node = DotAppNode::new("test").with_start(Instant::now() - std::time::Duration::from_secs(5));
+        assert!(call(&node, METH_UPTIME).unwrap().unwrap().as_i64() >= 5);
+        assert!(call(&DotAppNode::new("test"), METH_VERSION).unwrap().unwrap().is_null());
+    }
+
+    #[test]
+    #[should_panic]
+    fn dot_app_rejects_duplicate_method() {
+        let _ = DotAppNode::new("test").with_method(&DOT_APP_METHODS[0], |_| Ok(RpcValue::null()));
+    }
+
+    #[test]
+    fn device_alerts_emit_alertsmod() {
+        let (client_cmd_tx, mut receiver) = command_channel();
+        let device = DotDeviceNode::new("device", "1.0", None);
+        let alerts = device.alerts();
+        alerts.attach(".device", client_cmd_tx);
+
+        alerts.raise(Alert::new("overheat", 30).with_info("85 C"));
+        alerts.raise(Alert::new("overheat", 50));
+        assert_eq!(alerts.get().len(), 1);
+        assert_eq!(alerts.get()[0].level, 50);
+        assert!(alerts.clear("overheat"));
+        assert!(!alerts.clear("overheat"));
+
+        for count in [1, 1, 0] {
+            assert_eq!(expect_signal(&mut receiver, ".device", SIG_ALERTSMOD).as_list().len(), count);
+        }
+        assert!(receiver.try_next().is_err());
+    }
+}
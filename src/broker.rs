@@ -0,0 +1,388 @@
+//! In-process loopback broker
+//!
+//! A minimal broker for integration tests of devices and their consumers
+//! running in a single process. It implements login, mounting, request
+//! routing by path, response routing by caller IDs and signal fan-out
+//! according to the `.broker/app:subscribe` requests.
+//!
+//! Clients connect to the broker by `Client::run_loopback()`. The user
+//! and password are taken from the URL in the client config, the mount
+//! point from the `mount` field.
+
+use crate::acl::AccessRules;
+use crate::client::Sender;
+use crate::clientnode::{find_longest_prefix, process_local_dir_ls, RequestResult, METH_PING};
+use crate::connection::{ConnectionCommand, ConnectionEvent};
+use futures::StreamExt;
+use log::*;
+use shvrpc::client::ClientConfig;
+use shvrpc::metamethod::AccessLevel;
+use shvrpc::rpcmessage::{PeerId, RpcError, RpcErrorCode};
+use shvrpc::util::login_from_url;
+use shvrpc::{RpcMessage, RpcMessageMetaTags};
+use shvproto::RpcValue;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+const BROKER_APP_PATH: &str = ".broker/app";
+const APP_PATH: &str = ".app";
+const METH_SUBSCRIBE: &str = "subscribe";
+const METH_UNSUBSCRIBE: &str = "unsubscribe";
+const METH_NAME: &str = "name";
+
+struct User {
+    password: String,
+    access: AccessLevel,
+}
+
+struct Peer {
+    mount_point: Option<String>,
+    access: AccessLevel,
+    conn_evt_tx: Sender<ConnectionEvent>,
+    // (path, signal)
+    subscriptions: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+struct BrokerState {
+    users: HashMap<String, User>,
+    peers: BTreeMap<PeerId, Peer>,
+    // mount point -> peer ID
+    mounts: BTreeMap<String, PeerId>,
+    next_peer_id: PeerId,
+}
+
+#[derive(Clone, Default)]
+pub struct LoopbackBroker {
+    state: Arc<Mutex<BrokerState>>,
+}
+
+impl LoopbackBroker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add_user(&self, user: impl Into<String>, password: impl Into<String>, access: AccessLevel) -> &Self {
+        self.lock().users.insert(user.into(), User { password: password.into(), access });
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BrokerState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn login(&self, config: &ClientConfig, conn_evt_tx: Sender<ConnectionEvent>) -> shvrpc::Result<PeerId> {
+        let url = Url::parse(&config.url)?;
+        let (user, password) = login_from_url(&url);
+        let mut state = self.lock();
+        let access = match state.users.get(&user) {
+            Some(u) if u.password == password => u.access,
+            _ => return Err(format!("Login failed for user '{user}'").into()),
+        };
+        let mount_point = config.mount.clone().filter(|mount| !mount.is_empty());
+        if let Some(mount_point) = &mount_point {
+            if state.mounts.contains_key(mount_point) {
+                return Err(format!("Mount point '{mount_point}' is already in use").into());
+            }
+        }
+        state.next_peer_id += 1;
+        let peer_id = state.next_peer_id;
+        if let Some(mount_point) = &mount_point {
+            state.mounts.insert(mount_point.clone(), peer_id);
+        }
+        state.peers.insert(peer_id, Peer {
+            mount_point,
+            access,
+            conn_evt_tx,
+            subscriptions: Vec::new(),
+        });
+        debug!("Loopback broker: peer {peer_id} logged in as '{user}'");
+        Ok(peer_id)
+    }
+
+    fn logout(&self, peer_id: PeerId) {
+        let mut state = self.lock();
+        if let Some(peer) = state.peers.remove(&peer_id) {
+            if let Some(mount_point) = peer.mount_point {
+                state.mounts.remove(&mount_point);
+            }
+        }
+        debug!("Loopback broker: peer {peer_id} logged out");
+    }
+
+    // Emulates the connection task of a client connected to the broker
+    pub(crate) async fn peer_task(self, config: ClientConfig, conn_evt_tx: Sender<ConnectionEvent>) {
+        let peer_id = match self.login(&config, conn_evt_tx.clone()) {
+            Ok(peer_id) => peer_id,
+            Err(err) => {
+                error!("Loopback broker: {err}");
+                return;
+            }
+        };
+        let (conn_cmd_tx, mut conn_cmd_rx) = futures::channel::mpsc::unbounded();
+        if conn_evt_tx.unbounded_send(ConnectionEvent::Connected(conn_cmd_tx)).is_ok() {
+            while let Some(ConnectionCommand::SendMessage(message)) = conn_cmd_rx.next().await {
+                self.lock().process_message(peer_id, message);
+            }
+        }
+        self.logout(peer_id);
+        let _ = conn_evt_tx.unbounded_send(ConnectionEvent::Disconnected);
+    }
+}
+
+fn send_to_peer(peer: &Peer, message: &RpcMessage) {
+    match message.to_frame() {
+        Ok(frame) => {
+            if peer.conn_evt_tx.unbounded_send(ConnectionEvent::RpcFrameReceived(frame)).is_err() {
+                warn!("Loopback broker: peer connection closed, message dropped: {message}");
+            }
+        }
+        Err(err) => error!("Loopback broker: cannot convert message to frame: {err}"),
+    }
+}
+
+fn subscription_param(param: Option<&RpcValue>) -> Option<(String, String)> {
+    let map = param?.as_map();
+    let signal = map.get("signal").map_or("chng", RpcValue::as_str);
+    let paths = map.get("paths").map_or("", RpcValue::as_str);
+    Some((paths.to_string(), signal.to_string()))
+}
+
+fn path_matches(subscribed_path: &str, path: &str) -> bool {
+    subscribed_path.is_empty() || path.strip_prefix(subscribed_path)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl BrokerState {
+    fn process_message(&mut self, peer_id: PeerId, message: RpcMessage) {
+        if message.is_request() {
+            self.process_request(peer_id, message);
+        } else if message.is_response() {
+            self.process_response(message);
+        } else if message.is_signal() {
+            self.process_signal(peer_id, message);
+        }
+    }
+
+    fn respond(&self, peer_id: PeerId, request: &RpcMessage, result: Result<RpcValue, RpcError>) {
+        let Some(peer) = self.peers.get(&peer_id) else {
+            return;
+        };
+        let Ok(mut resp) = request.prepare_response() else {
+            warn!("Loopback broker: cannot prepare response for: {request}");
+            return;
+        };
+        match result {
+            Ok(r) => resp.set_result(r),
+            Err(e) => resp.set_error(e),
+        };
+        send_to_peer(peer, &resp);
+    }
+
+    fn process_broker_request(&mut self, peer_id: PeerId, request: &RpcMessage) -> Option<Result<RpcValue, RpcError>> {
+        let path = request.shv_path().unwrap_or_default();
+        let method = request.method().unwrap_or_default();
+        match (path, method) {
+            (APP_PATH, METH_PING) | (BROKER_APP_PATH, METH_PING) => Some(Ok(().into())),
+            (APP_PATH, METH_NAME) => Some(Ok("shvclient-loopback-broker".into())),
+            (BROKER_APP_PATH, METH_SUBSCRIBE) | (BROKER_APP_PATH, METH_UNSUBSCRIBE) => {
+                let Some(subscription) = subscription_param(request.param()) else {
+                    return Some(Err(RpcError::new(RpcErrorCode::InvalidParam, "Invalid subscription parameter")));
+                };
+                let peer = self.peers.get_mut(&peer_id)?;
+                if method == METH_SUBSCRIBE {
+                    if !peer.subscriptions.contains(&subscription) {
+                        peer.subscriptions.push(subscription);
+                    }
+                    Some(Ok(true.into()))
+                } else {
+                    let count = peer.subscriptions.len();
+                    peer.subscriptions.retain(|s| s != &subscription);
+                    Some(Ok((peer.subscriptions.len() != count).into()))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn process_request(&mut self, peer_id: PeerId, mut request: RpcMessage) {
+        if let Some(result) = self.process_broker_request(peer_id, &request) {
+            self.respond(peer_id, &request, result);
+            return;
+        }
+        let Ok(frame) = request.to_frame() else {
+            warn!("Loopback broker: invalid request: {request}");
+            return;
+        };
+        match process_local_dir_ls(&self.mounts, &frame, &AccessRules::default()) {
+            Some(RequestResult::Response(r)) => {
+                self.respond(peer_id, &request, Ok(r));
+                return;
+            }
+            Some(RequestResult::Error(e)) => {
+                self.respond(peer_id, &request, Err(e));
+                return;
+            }
+            None => { }
+        }
+        let shv_path = request.shv_path().unwrap_or_default().to_string();
+        let Some((mount_point, rest)) = find_longest_prefix(&self.mounts, &shv_path) else {
+            let method = request.method().unwrap_or_default().to_string();
+            self.respond(peer_id, &request, Err(RpcError::new(
+                        RpcErrorCode::MethodNotFound,
+                        format!("Invalid shv path {shv_path}:{method}()"))));
+            return;
+        };
+        let Some(access) = self.peers.get(&peer_id).map(|peer| peer.access) else {
+            return;
+        };
+        let Some(target) = self.mounts.get(mount_point).and_then(|id| self.peers.get(id)) else {
+            return;
+        };
+        request.set_shvpath(rest);
+        request.set_access_level(access);
+        let mut caller_ids = request.caller_ids();
+        caller_ids.push(peer_id);
+        request.set_caller_ids(&caller_ids);
+        send_to_peer(target, &request);
+    }
+
+    fn process_response(&mut self, mut response: RpcMessage) {
+        let mut caller_ids = response.caller_ids();
+        let Some(caller_id) = caller_ids.pop() else {
+            warn!("Loopback broker: response without caller ID: {response}");
+            return;
+        };
+        response.set_caller_ids(&caller_ids);
+        match self.peers.get(&caller_id) {
+            Some(peer) => send_to_peer(peer, &response),
+            None => debug!("Loopback broker: response for disconnected peer {caller_id} dropped"),
+        }
+    }
+
+    fn process_signal(&mut self, peer_id: PeerId, mut signal: RpcMessage) {
+        let Some(mount_point) = self.peers.get(&peer_id).and_then(|peer| peer.mount_point.clone()) else {
+            warn!("Loopback broker: signal from an unmounted peer {peer_id} dropped");
+            return;
+        };
+        let path = match signal.shv_path().unwrap_or_default() {
+            "" => mount_point,
+            path => format!("{mount_point}/{path}"),
+        };
+        let method = signal.method().unwrap_or_default().to_string();
+        signal.set_shvpath(&path);
+        self.peers
+            .values()
+            .filter(|peer| peer.subscriptions
+                .iter()
+                .any(|(sub_path, sub_signal)| sub_signal == &method && path_matches(sub_path, &path)))
+            .for_each(|peer| send_to_peer(peer, &signal));
+    }
+}
+
+impl std::fmt::Debug for LoopbackBroker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.lock();
+        f.debug_struct("LoopbackBroker")
+            .field("peers", &state.peers.keys().collect::<Vec<_>>())
+            .field("mounts", &state.mounts)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use crate::appnodes::DotAppNode;
+    use crate::clientnode::SIG_CHNG;
+    use crate::{Client, ClientCommandSender, ClientEvent, ClientEventsReceiver};
+    use futures::future::Either;
+    use futures_time::future::FutureExt;
+    use futures_time::time::Duration;
+
+    fn config(mount: Option<&str>) -> ClientConfig {
+        ClientConfig {
+            url: "tcp://test@localhost?password=secret".into(),
+            mount: mount.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    async fn wait_connected(rx: &mut futures::channel::mpsc::UnboundedReceiver<(ClientCommandSender, ClientEventsReceiver)>) -> ClientCommandSender {
+        let (client_cmd_tx, mut client_evt_rx) = rx.next().await.expect("Client init");
+        let ClientEvent::Connected = client_evt_rx.wait_for_event().await.expect("Client event receive") else {
+            panic!("Expected Connected client event");
+        };
+        client_cmd_tx
+    }
+
+    #[test]
+    fn route_requests_and_signals() {
+        let _ = simple_logger::init_with_level(Level::Debug);
+
+        let broker = LoopbackBroker::new();
+        broker.add_user("test", "secret", AccessLevel::Superuser);
+
+        let (device_init_tx, mut device_init_rx) = futures::channel::mpsc::unbounded();
+        let (consumer_init_tx, mut consumer_init_rx) = futures::channel::mpsc::unbounded();
+
+        let mut device = Client::<()>::new(DotAppNode::new("device"));
+        let mut consumer = Client::<()>::new(DotAppNode::new("consumer"));
+        let device_config = config(Some("test/device"));
+        let consumer_config = config(None);
+
+        let test = async move {
+            let device_cmd_tx = wait_connected(&mut device_init_rx).await;
+            let consumer_cmd_tx = wait_connected(&mut consumer_init_rx).await;
+
+            let mut resp_rx = consumer_cmd_tx
+                .do_rpc_call("test/device/.app", "name")
+                .expect("RpcCall command send");
+            let resp = resp_rx.next().await.unwrap().to_rpcmesage().unwrap();
+            assert_eq!(resp.result().expect("Response should be Ok").as_str(), "device");
+
+            let mut resp_rx = consumer_cmd_tx
+                .do_rpc_call("test/nonexisting", "get")
+                .expect("RpcCall command send");
+            let resp = resp_rx.next().await.unwrap().to_rpcmesage().unwrap();
+            assert_eq!(resp.result().expect_err("Response should be Err").code, RpcErrorCode::MethodNotFound);
+
+            let mut notify_rx = consumer_cmd_tx
+                .subscribe("test/device/status", SIG_CHNG)
+                .expect("ClientCommand subscribe send");
+            // The broker processes the messages of a peer in order, so
+            // the subscription is active when the response arrives.
+            let mut resp_rx = consumer_cmd_tx
+                .do_rpc_call(".app", "ping")
+                .expect("RpcCall command send");
+            resp_rx.next().await.unwrap();
+
+            device_cmd_tx
+                .send_message(RpcMessage::new_signal("status/value", SIG_CHNG, Some(42.into())))
+                .expect("Signal send");
+            let sig = notify_rx.recv().await.unwrap().to_rpcmesage().unwrap();
+            assert_eq!(sig.shv_path(), Some("test/device/status/value"));
+            assert_eq!(sig.param(), Some(&RpcValue::from(42)));
+        };
+
+        ::tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let device_run = device.run_loopback_with_init(&broker, &device_config, move |tx, rx| {
+                    device_init_tx.unbounded_send((tx, rx)).unwrap();
+                });
+                let consumer_run = consumer.run_loopback_with_init(&broker, &consumer_config, move |tx, rx| {
+                    consumer_init_tx.unbounded_send((tx, rx)).unwrap();
+                });
+                let test = test.timeout(Duration::from_secs(5));
+                futures::pin_mut!(device_run, consumer_run, test);
+                match futures::future::select(futures::future::select(device_run, consumer_run), test).await {
+                    Either::Left(_) => panic!("Client loop terminated"),
+                    Either::Right((res, _)) => res.expect("Test timeout"),
+                }
+            });
+    }
+}
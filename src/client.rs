@@ -1,5 +1,17 @@
-use crate::connection::{spawn_connection_task, ConnectionCommand, ConnectionEvent};
-use crate::clientnode::{find_longest_prefix, process_local_dir_ls, Route, ClientNode, RequestResult};
+use crate::acl::AccessRules;
+use crate::alarms::Alarms;
+use crate::journal::Journal;
+use crate::connection::{spawn_connection_task, ConnectionCommand, ConnectionEvent, FrameObserver, FrameObserverRef};
+use crate::clientnode::{find_longest_prefix, join_path, process_local_dir_ls, send_response, RequestAccess, Route, ClientNode, RequestResult, ShvNode, SIG_CHNG};
+use crate::middleware::Middleware;
+use crate::offlinebuffer::{OfflineBuffer, OfflineBufferOptions, OfflineBufferStats};
+use crate::propertynode::{PropertyNode, PropertyValue};
+use crate::recording::{replay_task, ReplayOptions, TrafficRecording};
+use crate::runtime::spawn_task;
+#[cfg(any(test, feature = "testing"))]
+use crate::testing::MockConnector;
+#[cfg(feature = "loopback_broker")]
+use crate::broker::LoopbackBroker;
 use async_broadcast::RecvError;
 use futures::future::BoxFuture;
 use futures::{select, Future, FutureExt, StreamExt};
@@ -14,6 +26,8 @@ use shvproto::RpcValue;
 use std::collections::{BTreeMap, HashMap};
 use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 const METH_SUBSCRIBE: &str = "subscribe";
 const METH_UNSUBSCRIBE: &str = "unsubscribe";
@@ -63,9 +77,29 @@ impl Drop for NotificationsReceiver {
 #[derive(Clone)]
 pub struct ClientCommandSender {
     pub(crate) sender: Sender<ClientCommand>,
+    pub(crate) response_guard: Option<Arc<ResponseGuard>>,
 }
 
 impl ClientCommandSender {
+    /// Returns a sender that ensures the request gets exactly one response.
+    /// If the sender and all its clones get dropped without sending
+    /// the response, an error response is sent instead. A clone kept by
+    /// the handler, e.g. in a spawned task, postpones the error response
+    /// until the clone is dropped.
+    pub(crate) fn with_response_guard(&self, request: &RpcMessage) -> Self {
+        let response_guard = request.prepare_response().ok().map(|response| Arc::new(ResponseGuard {
+            response: Some(response),
+            path: request.shv_path().unwrap_or_default().to_owned(),
+            method: request.method().unwrap_or_default().to_owned(),
+            sender: self.sender.clone(),
+            responded: AtomicBool::new(false),
+        }));
+        Self {
+            sender: self.sender.clone(),
+            response_guard,
+        }
+    }
+
     pub fn do_rpc_call_param<'a>(&self, shvpath: impl Into<&'a str>, method: impl Into<&'a str>, param: Option<RpcValue>) -> Result<Receiver<RpcFrame>, TrySendError<ClientCommand>> {
         let (response_sender, response_receiver) = futures::channel::mpsc::unbounded();
         self.sender.unbounded_send(ClientCommand::RpcCall {
@@ -79,7 +113,16 @@ impl ClientCommandSender {
         self.do_rpc_call_param(shvpath, method, None)
     }
 
+    pub(crate) fn response_sent(&self) -> bool {
+        self.response_guard
+            .as_ref()
+            .is_some_and(|guard| guard.responded.load(Ordering::Relaxed))
+    }
+
     pub fn send_message(&self, message: RpcMessage) -> Result<(), TrySendError<ClientCommand>> {
+        if let Some(guard) = &self.response_guard {
+            guard.check_response(&message);
+        }
         self.sender.unbounded_send(ClientCommand::SendMessage { message })
     }
 
@@ -107,6 +150,54 @@ impl ClientCommandSender {
     }
 }
 
+pub(crate) struct ResponseGuard {
+    response: Option<RpcMessage>,
+    path: String,
+    method: String,
+    sender: Sender<ClientCommand>,
+    responded: AtomicBool,
+}
+
+impl ResponseGuard {
+    fn check_response(&self, message: &RpcMessage) {
+        let Some(response) = &self.response else {
+            return;
+        };
+        // Request IDs are unique only per caller
+        if !message.is_response()
+            || message.request_id() != response.request_id()
+            || message.caller_ids() != response.caller_ids()
+        {
+            return;
+        }
+        if self.responded.swap(true, Ordering::Relaxed) {
+            warn!("Multiple responses sent to request {}, path: {}, method: {}",
+                message.request_id().unwrap_or_default(),
+                self.path,
+                self.method);
+        }
+    }
+}
+
+impl Drop for ResponseGuard {
+    fn drop(&mut self) {
+        if *self.responded.get_mut() {
+            return;
+        }
+        let Some(mut response) = self.response.take() else {
+            return;
+        };
+        error!("No response sent to request {}, path: {}, method: {}",
+            response.request_id().unwrap_or_default(),
+            self.path,
+            self.method);
+        response.set_error(RpcError::new(RpcErrorCode::MethodCallException, "No response sent by the method handler"));
+        if let Err(err) = self.sender.unbounded_send(ClientCommand::SendMessage { message: response }) {
+            error!("Cannot send response. Error: {err}");
+        }
+    }
+}
+
 pub enum ClientCommand {
     SendMessage {
         message: RpcMessage,
@@ -126,9 +217,20 @@ pub enum ClientCommand {
         signal: String,
         subscription_id: u64,
     },
+    // Sent by the library when a request handler panics
+    #[non_exhaustive]
+    HandlerPanicked {
+        path: String,
+        method: String,
+        message: String,
+    },
+    // Acknowledged by the client loop, the tests sync with the loop by it
+    #[cfg(test)]
+    Sync(futures::channel::oneshot::Sender<()>),
 }
 
 const BROKER_APP_NODE: &str = ".broker/app";
+const DEVICE_NODE_PATH: &str = ".device";
 
 // The wrapping struct itself is descriptive
 #[allow(clippy::type_complexity)]
@@ -146,7 +248,116 @@ impl<T> MethodsGetter<T> {
 
 // The wrapping struct itself is descriptive
 #[allow(clippy::type_complexity)]
-pub struct RequestHandler<T>(pub(crate) Box<dyn Fn(RpcMessage, ClientCommandSender, Option<AppState<T>>) -> BoxFuture<'static, ()> + Sync + Send>);
+pub struct ChildrenGetter<T>(pub(crate) Box<dyn Fn(String, Option<AppState<T>>) -> BoxFuture<'static, Option<Vec<String>>> + Sync + Send>);
+
+impl<T> ChildrenGetter<T> {
+    pub fn new<F, Fut>(func: F) -> Self
+    where
+        F: Fn(String, Option<AppState<T>>) -> Fut + Sync + Send + 'static,
+        Fut: Future<Output=Option<Vec<String>>> + Send + 'static,
+    {
+        Self(Box::new(move |path, data| Box::pin(func(path, data))))
+    }
+}
+
+/// Context of a request passed to a request handler
+///
+/// Besides the request itself, it carries the mount path of the node,
+/// the method resolved from the node's methods and the means to send
+/// the response. Responding consumes the context.
+pub struct RequestContext<T> {
+    request: RpcMessage,
+    client_cmd_tx: ClientCommandSender,
+    app_state: Option<AppState<T>>,
+    mount_path: String,
+    method: Option<MetaMethod>,
+    access: RequestAccess,
+}
+
+impl<T> RequestContext<T> {
+    pub(crate) fn new(
+        request: RpcMessage,
+        access: RequestAccess,
+        client_cmd_tx: ClientCommandSender,
+        app_state: Option<AppState<T>>,
+        mount_path: String,
+        method: Option<&MetaMethod>,
+    ) -> Self {
+        let method = method.map(|mm| MetaMethod {
+            name: mm.name,
+            flags: mm.flags,
+            access: mm.access,
+            param: mm.param,
+            result: mm.result,
+            description: mm.description,
+        });
+        Self { request, client_cmd_tx, app_state, mount_path, method, access }
+    }
+
+    pub fn request(&self) -> &RpcMessage {
+        &self.request
+    }
+
+    pub fn client_cmd_tx(&self) -> &ClientCommandSender {
+        &self.client_cmd_tx
+    }
+
+    pub fn app_state(&self) -> Option<&AppState<T>> {
+        self.app_state.as_ref()
+    }
+
+    /// Path of the node the request has been routed to
+    pub fn mount_path(&self) -> &str {
+        &self.mount_path
+    }
+
+    /// Path of the request relative to the mount path
+    pub fn path(&self) -> &str {
+        self.request.shv_path().unwrap_or_default()
+    }
+
+    /// Full path of the request
+    pub fn full_path(&self) -> String {
+        join_path(&self.mount_path, self.path())
+    }
+
+    /// The called method as listed by the node
+    pub fn method(&self) -> Option<&MetaMethod> {
+        self.method.as_ref()
+    }
+
+    pub fn access(&self) -> &RequestAccess {
+        &self.access
+    }
+
+    pub fn access_level(&self) -> Option<i32> {
+        self.access.level()
+    }
+
+    pub fn user_id(&self) -> Option<&str> {
+        self.request.user_id()
+    }
+
+    pub fn respond(self, result: impl Into<RpcValue>) {
+        send_response(self.request, self.client_cmd_tx, Ok(result.into()));
+    }
+
+    pub fn respond_error(self, error: RpcError) {
+        send_response(self.request, self.client_cmd_tx, Err(error));
+    }
+
+    pub fn respond_result(self, result: Result<RpcValue, RpcError>) {
+        send_response(self.request, self.client_cmd_tx, result);
+    }
+
+    pub fn into_parts(self) -> (RpcMessage, ClientCommandSender, Option<AppState<T>>) {
+        (self.request, self.client_cmd_tx, self.app_state)
+    }
+}
+
+// The wrapping struct itself is descriptive
+#[allow(clippy::type_complexity)]
+pub struct RequestHandler<T>(pub(crate) Box<dyn Fn(RequestContext<T>) -> BoxFuture<'static, ()> + Sync + Send>);
 
 impl<T> RequestHandler<T> {
     pub fn stateful<F, Fut>(func: F) -> Self
@@ -154,7 +365,10 @@ impl<T> RequestHandler<T> {
         F: Fn(RpcMessage, ClientCommandSender, Option<AppState<T>>) -> Fut + Sync + Send + 'static,
         Fut: Future<Output=()> + Send + 'static
     {
-        Self(Box::new(move |req, tx, data| Box::pin(func(req, tx, data))))
+        Self(Box::new(move |ctx| {
+            let (req, tx, data) = ctx.into_parts();
+            Box::pin(func(req, tx, data))
+        }))
     }
 
     pub fn stateless<F, Fut>(func: F) -> Self
@@ -162,7 +376,18 @@ impl<T> RequestHandler<T> {
         F: Fn(RpcMessage, ClientCommandSender) -> Fut + Sync + Send + 'static,
         Fut: Future<Output=()> + Send + 'static
     {
-        Self(Box::new(move |req, tx, _data| Box::pin(func(req, tx))))
+        Self(Box::new(move |ctx| {
+            let (req, tx, _data) = ctx.into_parts();
+            Box::pin(func(req, tx))
+        }))
+    }
+
+    pub fn with_context<F, Fut>(func: F) -> Self
+    where
+        F: Fn(RequestContext<T>) -> Fut + Sync + Send + 'static,
+        Fut: Future<Output=()> + Send + 'static
+    {
+        Self(Box::new(move |ctx| Box::pin(func(ctx))))
     }
 }
 
@@ -171,6 +396,12 @@ pub enum ClientEvent {
     /// Client core broadcasts this event when connected to a broker
     Connected,
     Disconnected,
+    /// A request handler panicked. The caller got an error response.
+    HandlerPanicked {
+        path: String,
+        method: String,
+        message: String,
+    },
 }
 
 pub struct ClientEventsReceiver(BroadcastReceiver<ClientEvent>);
@@ -250,7 +481,7 @@ impl Subscriptions {
         let added_new = path_signal_subscriptions.is_empty();
 
         if path_signal_subscriptions.insert(subscription_id, notifications_sender).is_some() {
-            panic!("BUG: Subscription with the same ID {} for path: {}, method: {}. Dump: {:?}",
+            error!("Subscription with the same ID {} for path: {}, method: {}. Dump: {:?}",
                 subscription_id, path, signal, &self);
         }
 
@@ -286,21 +517,50 @@ impl Subscriptions {
 pub struct Client<T> {
     mounts: BTreeMap<String, ClientNode<'static, T>>,
     app_state: Option<AppState<T>>,
+    access_rules: Arc<AccessRules>,
+    journal: Option<Journal>,
+    offline_buffer: Option<OfflineBuffer>,
+    middlewares: Vec<Box<dyn Middleware>>,
+    // called whenever the client connects to a broker
+    connected_hooks: Vec<Box<dyn Fn() + Send + Sync>>,
+    // paths whose `chng` is sent again by the connected hooks
+    announced_paths: Vec<String>,
+    frame_observer: Option<FrameObserverRef>,
+    client_cmd_tx: ClientCommandSender,
+    // the uptime of `.app` and `.device` is measured from here
+    created: Instant,
+    // locked by the running client loop, the lock is released also when
+    // the future of the loop is dropped
+    client_cmd_rx: Arc<futures::lock::Mutex<Receiver<ClientCommand>>>,
 }
 
 impl<T: Send + Sync + 'static> Client<T> {
     pub fn new(app_node: crate::appnodes::DotAppNode) -> Self {
+        let (client_cmd_tx, client_cmd_rx) = futures::channel::mpsc::unbounded();
         let mut client = Self {
             mounts: Default::default(),
             app_state: Default::default(),
+            access_rules: Default::default(),
+            journal: Default::default(),
+            offline_buffer: Default::default(),
+            middlewares: Default::default(),
+            connected_hooks: Default::default(),
+            announced_paths: Default::default(),
+            frame_observer: Default::default(),
+            client_cmd_tx: ClientCommandSender { sender: client_cmd_tx, response_guard: None },
+            client_cmd_rx: Arc::new(futures::lock::Mutex::new(client_cmd_rx)),
+            created: Instant::now(),
         };
+        let app_node = app_node.with_start(client.created);
         client.mount(".app", ClientNode::constant(app_node));
         client
     }
 
     pub fn new_device(app_node: crate::appnodes::DotAppNode, device_node: crate::appnodes::DotDeviceNode) -> Self {
         let mut client = Self::new(app_node);
-        client.mount(".device", ClientNode::constant(device_node));
+        device_node.alerts().attach(DEVICE_NODE_PATH, client.command_sender());
+        let device_node = device_node.with_start(client.created);
+        client.mount(DEVICE_NODE_PATH, device_node.into_client_node());
         client
     }
 
@@ -309,6 +569,15 @@ impl<T: Send + Sync + 'static> Client<T> {
         self
     }
 
+    pub fn mount_node<P, N>(&mut self, path: P, node: N) -> &mut Self
+    where
+        P: Into<String>,
+        N: ShvNode<T> + 'static,
+    {
+        self.mounts.insert(path.into(), ClientNode::new(node));
+        self
+    }
+
     pub fn mount_fixed<P, M, R>(&mut self, path: P, defined_methods: M, routes: R) -> &mut Self
     where
         P: Into<String>,
@@ -327,11 +596,92 @@ impl<T: Send + Sync + 'static> Client<T> {
         self
     }
 
+    /// Mounts alarms, which are announced again on every connection
+    ///
+    /// The announcement carries the current alarms, so the `chng` of the
+    /// alarms kept by the offline buffer is not sent after reconnecting.
+    pub fn mount_alarms<P: Into<String>>(&mut self, path: P, alarms: &Alarms) -> &mut Self {
+        let path = path.into();
+        alarms.attach(&path, self.command_sender());
+        let announced_alarms = alarms.clone();
+        self.connected_hooks.push(Box::new(move || announced_alarms.announce()));
+        self.announced_paths.push(path.clone());
+        self.mounts.insert(path, alarms.client_node());
+        self
+    }
+
+    /// Mounts a journal serving `getLog`, the journal records every `chng`
+    /// signal sent by the client
+    pub fn mount_journal<P: Into<String>>(&mut self, path: P, journal: &Journal) -> &mut Self {
+        self.journal = Some(journal.clone());
+        self.mounts.insert(path.into(), journal.client_node());
+        self
+    }
+
+    pub fn mount_property<P, V>(&mut self, path: P, property: &PropertyNode<V>) -> &mut Self
+    where
+        P: Into<String>,
+        V: PropertyValue,
+    {
+        let path = path.into();
+        property.attach(&path, self.command_sender());
+        self.mounts.insert(path, property.client_node());
+        self
+    }
+
+    /// Returns a sender of the client commands
+    ///
+    /// The sender can be used before the client is run. The commands
+    /// are processed once the client loop is started.
+    pub fn command_sender(&self) -> ClientCommandSender {
+        self.client_cmd_tx.clone()
+    }
+
     pub fn with_app_state(&mut self, app_state: AppState<T>) -> &mut Self {
         self.app_state = Some(app_state);
         self
     }
 
+    /// Sets access rules enforced on the requests in addition to the access
+    /// levels of the methods of the mounted nodes. Nodes that the rules do not
+    /// allow to `ls` are also left out from the `ls` of their parent.
+    pub fn with_access_rules(&mut self, access_rules: AccessRules) -> &mut Self {
+        self.access_rules = Arc::new(access_rules);
+        self
+    }
+
+    /// Keeps the signals, and optionally the requests, sent while the client
+    /// is disconnected and sends them after the client connects again
+    ///
+    /// The buffered messages are sent before the nodes announce their state
+    /// on the connection, e.g. the mounted alarms. The buffered `chng` of
+    /// such nodes is dropped, as the announcement supersedes it.
+    pub fn with_offline_buffer(&mut self, options: OfflineBufferOptions) -> &mut Self {
+        self.offline_buffer = Some(OfflineBuffer::new(options));
+        self
+    }
+
+    /// Returns the counters of the messages dropped by the offline buffer
+    pub fn offline_buffer_stats(&self) -> Option<OfflineBufferStats> {
+        self.offline_buffer.as_ref().map(OfflineBuffer::stats)
+    }
+
+    pub fn add_middleware<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Middleware + 'static,
+    {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    pub fn with_frame_observer<O>(&mut self, frame_observer: O) -> &mut Self
+    where
+        O: FrameObserver + 'static,
+    {
+        self.frame_observer = Some(Arc::new(frame_observer));
+        self
+    }
+
     async fn run_with_init_opt<H>(
         &mut self,
         config: &ClientConfig,
@@ -341,7 +691,7 @@ impl<T: Send + Sync + 'static> Client<T> {
         H: FnOnce(ClientCommandSender, ClientEventsReceiver),
     {
         let (conn_evt_tx, conn_evt_rx) = futures::channel::mpsc::unbounded::<ConnectionEvent>();
-        spawn_connection_task(config, conn_evt_tx);
+        spawn_connection_task(config, conn_evt_tx, self.frame_observer.clone());
         self.client_loop(conn_evt_rx, init_handler).await
     }
 
@@ -360,8 +710,111 @@ impl<T: Send + Sync + 'static> Client<T> {
         self.run_with_init_opt(config, Some(handler)).await
     }
 
+    async fn replay_with_init_opt<H>(
+        &mut self,
+        recording: TrafficRecording,
+        options: ReplayOptions,
+        init_handler: Option<H>,
+    ) -> shvrpc::Result<()>
+    where
+        H: FnOnce(ClientCommandSender, ClientEventsReceiver),
+    {
+        let (conn_evt_tx, conn_evt_rx) = futures::channel::mpsc::unbounded::<ConnectionEvent>();
+        spawn_task(replay_task(recording, options, conn_evt_tx, self.frame_observer.clone()));
+        self.client_loop(conn_evt_rx, init_handler).await
+    }
+
+    /// Replays the received side of a recorded session without a network connection
+    ///
+    /// The messages sent by the client are passed to the frame observer, if any.
+    /// Returns after all the frames have been injected and the outgoing traffic
+    /// has settled down.
+    pub async fn replay(&mut self, recording: TrafficRecording, options: ReplayOptions) -> shvrpc::Result<()> {
+        self.replay_with_init_opt(
+            recording,
+            options,
+            Option::<fn(ClientCommandSender, ClientEventsReceiver)>::None,
+        )
+        .await
+    }
+
+    pub async fn replay_with_init<H>(&mut self, recording: TrafficRecording, options: ReplayOptions, handler: H) -> shvrpc::Result<()>
+    where
+        H: FnOnce(ClientCommandSender, ClientEventsReceiver),
+    {
+        self.replay_with_init_opt(recording, options, Some(handler)).await
+    }
+
+    #[cfg(feature = "loopback_broker")]
+    async fn run_loopback_with_init_opt<H>(
+        &mut self,
+        broker: &LoopbackBroker,
+        config: &ClientConfig,
+        init_handler: Option<H>,
+    ) -> shvrpc::Result<()>
+    where
+        H: FnOnce(ClientCommandSender, ClientEventsReceiver),
+    {
+        let (conn_evt_tx, conn_evt_rx) = futures::channel::mpsc::unbounded::<ConnectionEvent>();
+        spawn_task(broker.clone().peer_task(config.clone(), conn_evt_tx));
+        self.client_loop(conn_evt_rx, init_handler).await
+    }
+
+    /// Runs the client connected to an in-process loopback broker
+    #[cfg(feature = "loopback_broker")]
+    pub async fn run_loopback(&mut self, broker: &LoopbackBroker, config: &ClientConfig) -> shvrpc::Result<()> {
+        self.run_loopback_with_init_opt(
+            broker,
+            config,
+            Option::<fn(ClientCommandSender, ClientEventsReceiver)>::None,
+        )
+        .await
+    }
+
+    #[cfg(feature = "loopback_broker")]
+    pub async fn run_loopback_with_init<H>(&mut self, broker: &LoopbackBroker, config: &ClientConfig, handler: H) -> shvrpc::Result<()>
+    where
+        H: FnOnce(ClientCommandSender, ClientEventsReceiver),
+    {
+        self.run_loopback_with_init_opt(broker, config, Some(handler)).await
+    }
+
+    /// Runs the client with an emulated broker connection
+    ///
+    /// The handler gets a `MockConnector` to emulate connections and the
+    /// traffic on them. It works with any of the supported async runtimes.
+    #[cfg(any(test, feature = "testing"))]
+    pub async fn run_with_mock<H>(&mut self, handler: H) -> shvrpc::Result<()>
+    where
+        H: FnOnce(MockConnector, ClientCommandSender, ClientEventsReceiver),
+    {
+        let (conn_evt_tx, conn_evt_rx) = futures::channel::mpsc::unbounded::<ConnectionEvent>();
+        let connector = MockConnector::new(conn_evt_tx);
+        self.client_loop(
+            conn_evt_rx,
+            Some(move |client_cmd_tx, client_evt_rx| handler(connector, client_cmd_tx, client_evt_rx)),
+        )
+        .await
+    }
+
     async fn client_loop<H>(
         &mut self,
+        conn_events_rx: Receiver<ConnectionEvent>,
+        init_handler: Option<H>,
+    ) -> shvrpc::Result<()>
+    where
+        H: FnOnce(ClientCommandSender, ClientEventsReceiver),
+    {
+        let client_cmd_rx = self.client_cmd_rx.clone();
+        let Some(mut client_cmd_rx) = client_cmd_rx.try_lock() else {
+            return Err("Client loop is already running".into());
+        };
+        self.client_loop_impl(&mut client_cmd_rx, conn_events_rx, init_handler).await
+    }
+
+    async fn client_loop_impl<H>(
+        &mut self,
+        client_cmd_rx: &mut Receiver<ClientCommand>,
         mut conn_events_rx: Receiver<ConnectionEvent>,
         init_handler: Option<H>,
     ) -> shvrpc::Result<()>
@@ -371,8 +824,7 @@ impl<T: Send + Sync + 'static> Client<T> {
         let mut pending_rpc_calls: HashMap<i64, Sender<RpcFrame>> = HashMap::new();
         let mut subscriptions = Subscriptions::new();
 
-        let (client_cmd_tx, mut client_cmd_rx) = futures::channel::mpsc::unbounded();
-        let client_cmd_tx = ClientCommandSender { sender: client_cmd_tx };
+        let client_cmd_tx = self.command_sender();
         let (mut client_events_tx, client_events_rx) = async_broadcast::broadcast(10);
         client_events_tx.set_overflow(true);
         let client_events_receiver = ClientEventsReceiver(client_events_rx.clone());
@@ -392,10 +844,22 @@ impl<T: Send + Sync + 'static> Client<T> {
                         use ClientCommand::*;
                         match client_cmd {
                             SendMessage { message } => {
+                                if message.is_response() {
+                                    self.middlewares
+                                        .iter()
+                                        .for_each(|middleware| middleware.process_response(&message));
+                                }
+                                if let Some(journal) = &self.journal {
+                                    if message.is_signal() && message.method() == Some(SIG_CHNG) {
+                                        journal.record(message.shv_path().unwrap_or_default(), message.param().map_or_else(RpcValue::null, Clone::clone));
+                                    }
+                                }
                                 if let Some(ref conn_cmd_sender) = conn_cmd_sender {
                                     if let Err(e) = conn_cmd_sender.unbounded_send(ConnectionCommand::SendMessage(message)) {
                                         error!("Cannot send message through ConnectionCommand channel: {e}");
                                     }
+                                } else if let Some(offline_buffer) = &mut self.offline_buffer {
+                                    offline_buffer.push(message);
                                 }
                             },
                             RpcCall { request, response_sender } => {
@@ -408,9 +872,9 @@ impl<T: Send + Sync + 'static> Client<T> {
                             Subscribe { path, signal, subscription_id, notifications_sender } => {
                                 if subscriptions.add(&path, &signal, subscription_id, notifications_sender) {
                                     let request = create_subscription_request(&path, &signal, SubscriptionRequest::Subscribe);
-                                    client_cmd_tx
-                                        .send_message(request)
-                                        .expect("Cannot send subscription request through ClientCommand channel");
+                                    if let Err(err) = client_cmd_tx.send_message(request) {
+                                        error!("Cannot send subscription request through ClientCommand channel: {err}");
+                                    }
                                 } else {
                                     warn!("Path {} and signal {} have already been subscribed!", &path, &signal);
                                 }
@@ -418,16 +882,27 @@ impl<T: Send + Sync + 'static> Client<T> {
                             Unsubscribe { path, signal, subscription_id } => {
                                 if subscriptions.remove(&path, &signal, subscription_id) {
                                     let request = create_subscription_request(&path, &signal, SubscriptionRequest::Unsubscribe);
-                                    client_cmd_tx
-                                        .send_message(request)
-                                        .expect("Cannot send subscription request through ClientCommand channel");
+                                    if let Err(err) = client_cmd_tx.send_message(request) {
+                                        error!("Cannot send subscription request through ClientCommand channel: {err}");
+                                    }
                                 }
                             },
+                            HandlerPanicked { path, method, message } => {
+                                if let Err(err) = client_events_tx.try_broadcast(ClientEvent::HandlerPanicked { path, method, message }) {
+                                    error!("Client event `HandlerPanicked` broadcast error: {err}");
+                                }
+                            },
+                            #[cfg(test)]
+                            Sync(done) => {
+                                let _ = done.send(());
+                            },
                         }
                         next_client_cmd = client_cmd_rx.next().fuse();
                     },
                     None => {
-                        panic!("Couldn't get ClientCommand from the channel");
+                        // Cannot happen as long as the client holds `client_cmd_tx`
+                        warn!("Client command channel closed, exiting");
+                        return Ok(());
                     },
                 },
                 conn_event_result = next_conn_event => match conn_event_result {
@@ -435,15 +910,27 @@ impl<T: Send + Sync + 'static> Client<T> {
                         use ConnectionEvent::*;
                         match conn_event {
                             RpcFrameReceived(frame) => {
-                                self.process_rpc_frame(frame, &client_cmd_tx, &mut pending_rpc_calls, &mut subscriptions)
-                                    .await
-                                    .expect("Cannot process RPC frame");
+                                if let Err(err) = self.process_rpc_frame(frame, &client_cmd_tx, &mut pending_rpc_calls, &mut subscriptions).await {
+                                    error!("Cannot process RPC frame: {err}");
+                                }
                             },
                             Connected(sender) => {
+                                if let Some(offline_buffer) = &mut self.offline_buffer {
+                                    let announced_paths = &self.announced_paths;
+                                    let is_announced = |message: &RpcMessage| message.is_signal()
+                                        && message.method() == Some(SIG_CHNG)
+                                        && message.shv_path().is_some_and(|path| announced_paths.iter().any(|announced| announced == path));
+                                    for message in offline_buffer.drain().filter(|message| !is_announced(message)) {
+                                        if let Err(e) = sender.unbounded_send(ConnectionCommand::SendMessage(message)) {
+                                            error!("Cannot send buffered message through ConnectionCommand channel: {e}");
+                                        }
+                                    }
+                                }
                                 conn_cmd_sender = Some(sender);
                                 if let Err(err) = client_events_tx.try_broadcast(ClientEvent::Connected) {
                                     error!("Client event `Connected` broadcast error: {err}");
                                 }
+                                self.connected_hooks.iter().for_each(|hook| hook());
                             },
                             Disconnected => {
                                 conn_cmd_sender = None;
@@ -479,14 +966,34 @@ impl<T: Send + Sync + 'static> Client<T> {
         if frame.is_request() {
             if let Ok(mut request_msg) = frame.to_rpcmesage() {
                 if let Ok(mut resp) = request_msg.prepare_response() {
+                    if let Some(result) = self.middlewares
+                        .iter()
+                        .find_map(|middleware| middleware.process_request(&request_msg))
+                    {
+                        match result {
+                            Ok(r) => resp.set_result(r),
+                            Err(e) => resp.set_error(e),
+                        };
+                        client_cmd_tx.send_message(resp)?;
+                        return Ok(());
+                    }
                     let shv_path = frame.shv_path().unwrap_or_default();
-                    let local_result = process_local_dir_ls(&self.mounts, &frame);
+                    let local_result = process_local_dir_ls(&self.mounts, &frame, &self.access_rules);
                     match local_result {
                         None => {
                             if let Some((mount, path)) = find_longest_prefix(&self.mounts, shv_path) {
                                 request_msg.set_shvpath(path);
-                                let node = self.mounts.get(mount).unwrap_or_else(|| panic!("A node on path '{mount}' should exist"));
-                                node.process_request(request_msg, mount.to_owned(), client_cmd_tx.clone(), &self.app_state).await;
+                                match self.mounts.get(mount) {
+                                    Some(node) => node.process_request(request_msg, mount.to_owned(), client_cmd_tx.clone(), &self.app_state, &self.access_rules),
+                                    None => {
+                                        error!("A node on path '{mount}' should exist");
+                                        resp.set_error(RpcError::new(
+                                            RpcErrorCode::InternalError,
+                                            format!("Node not found on path {mount}"),
+                                        ));
+                                        client_cmd_tx.send_message(resp)?;
+                                    }
+                                }
                             } else {
                                 let method = frame.method().unwrap_or_default();
                                 resp.set_error(RpcError::new(
@@ -574,58 +1081,13 @@ mod tests {
 
     mod drivers {
         use super::*;
-        use crate::appnodes::DotAppNode;
+        use crate::appnodes::{Alert, DotAppNode, DotDeviceNode};
         use futures_time::future::FutureExt;
         use futures_time::time::Duration;
-        use crate::clientnode::{SIG_CHNG, PROPERTY_METHODS};
+        use crate::clientnode::{DeferredResponse, SIG_CHNG, PROPERTY_METHODS};
+        use crate::testing::ConnectionMock;
         use shvrpc::metamethod::AccessLevel;
 
-        struct ConnectionMock {
-            conn_evt_tx: Sender<ConnectionEvent>,
-            conn_cmd_rx: Receiver<ConnectionCommand>,
-        }
-
-        impl Drop for ConnectionMock {
-            fn drop(&mut self) {
-                if self.conn_evt_tx.unbounded_send(ConnectionEvent::Disconnected).is_err() {
-                    error!("Disconnected event send error");
-                }
-            }
-        }
-
-        impl ConnectionMock {
-            fn new(conn_evt_tx: &Sender<ConnectionEvent>) -> Self {
-                let (conn_cmd_tx, conn_cmd_rx) = futures::channel::mpsc::unbounded::<ConnectionCommand>();
-                conn_evt_tx.unbounded_send(ConnectionEvent::Connected(conn_cmd_tx)).expect("Connected event send error");
-                Self {
-                    conn_evt_tx: conn_evt_tx.clone(),
-                    conn_cmd_rx,
-                }
-            }
-
-            fn emulate_receive_request(&self, request: RpcMessage) {
-                self.conn_evt_tx.unbounded_send(ConnectionEvent::RpcFrameReceived(request.to_frame().unwrap())).unwrap();
-            }
-
-            fn emulate_receive_response(&self, from_request: &RpcMessage, result: impl Into<RpcValue>) {
-                let mut resp = from_request.prepare_response().unwrap();
-                resp.set_result(result);
-                self.conn_evt_tx.unbounded_send(ConnectionEvent::RpcFrameReceived(resp.to_frame().unwrap())).unwrap();
-            }
-
-            fn emulate_receive_signal(&self, path: &str, sig_name: &str, param: Option<RpcValue>) {
-                let sig = RpcMessage::new_signal(path, sig_name, param);
-                self.conn_evt_tx.unbounded_send(ConnectionEvent::RpcFrameReceived(sig.to_frame().unwrap())).unwrap();
-            }
-
-            async fn expect_send_message(&mut self) -> RpcMessage {
-                let Some(ConnectionCommand::SendMessage(msg)) = self.conn_cmd_rx.next().await else {
-                    panic!("ConnectionCommand receive error");
-                };
-                msg
-            }
-        }
-
         async fn expect_client_connected(client_events_rx: &mut ClientEventsReceiver) {
             let ClientEvent::Connected = client_events_rx.wait_for_event().await.expect("Client event receive") else {
                 panic!("Expected Connected client event");
@@ -638,36 +1100,44 @@ mod tests {
             };
         }
 
+        // Returns when the client loop has processed the commands sent before,
+        // the commands are processed in order
+        async fn sync_client_commands(cli_cmd_tx: &ClientCommandSender) {
+            let (done_tx, done_rx) = futures::channel::oneshot::channel();
+            cli_cmd_tx.sender.unbounded_send(ClientCommand::Sync(done_tx)).expect("Sync command send");
+            done_rx.await.expect("Sync command processed");
+        }
+
         async fn init_connection(
-            conn_evt_tx: &Sender<ConnectionEvent>,
+            connector: &MockConnector,
             cli_evt_rx: &mut ClientEventsReceiver,
         ) -> ConnectionMock {
-            let conn_mock = ConnectionMock::new(conn_evt_tx);
+            let conn_mock = connector.connect();
             expect_client_connected(cli_evt_rx).await;
             conn_mock
         }
 
         pub(super) async fn receive_connected_and_disconnected_events(
-            conn_evt_tx: Sender<ConnectionEvent>,
+            connector: MockConnector,
             _cli_cmd_tx: ClientCommandSender,
             mut client_events_rx: ClientEventsReceiver,
         ) {
             {
-                let _conn_mock = ConnectionMock::new(&conn_evt_tx);
+                let _conn_mock = connector.connect();
                 expect_client_connected(&mut client_events_rx).await;
             }
             expect_client_disconnected(&mut client_events_rx).await;
 
-            let _conn_mock = ConnectionMock::new(&conn_evt_tx);
+            let _conn_mock = connector.connect();
             expect_client_connected(&mut client_events_rx).await;
         }
 
         pub(super) async fn send_message(
-            conn_evt_tx: Sender<ConnectionEvent>,
+            connector: MockConnector,
             cli_cmd_tx: ClientCommandSender,
             mut cli_evt_rx: ClientEventsReceiver,
         ) {
-            let mut conn_mock = init_connection(&conn_evt_tx, &mut cli_evt_rx).await;
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
 
             cli_cmd_tx.send_message(RpcMessage::new_request(
                     "path/test",
@@ -684,11 +1154,11 @@ mod tests {
         }
 
         pub(super) async fn send_message_fails(
-            conn_evt_tx: Sender<ConnectionEvent>,
+            connector: MockConnector,
             cli_cmd_tx: ClientCommandSender,
             mut cli_evt_rx: ClientEventsReceiver,
         ) {
-            let mut conn_mock = init_connection(&conn_evt_tx, &mut cli_evt_rx).await;
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
 
             cli_cmd_tx.send_message(RpcMessage::new_request(
                     "path/test",
@@ -713,11 +1183,11 @@ mod tests {
         }
 
         pub(super) async fn call_method_and_receive_response(
-            conn_evt_tx: Sender<ConnectionEvent>,
+            connector: MockConnector,
             cli_cmd_tx: ClientCommandSender,
             mut cli_evt_rx: ClientEventsReceiver,
         ) {
-            let mut conn_mock = init_connection(&conn_evt_tx, &mut cli_evt_rx).await;
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
             let mut resp_rx = cli_cmd_tx
                 .do_rpc_call("path/to/resource", "get")
                 .expect("RpcCall command send");
@@ -731,7 +1201,7 @@ mod tests {
         }
 
         pub(super) async fn call_method_timeouts_when_disconnected(
-            _conn_evt_tx: Sender<ConnectionEvent>,
+            _connector: MockConnector,
             cli_cmd_tx: ClientCommandSender,
             mut _cli_evt_rx: ClientEventsReceiver,
         ) {
@@ -757,11 +1227,11 @@ mod tests {
         }
 
         pub(super) async fn receive_subscribed_notification(
-            conn_evt_tx: Sender<ConnectionEvent>,
+            connector: MockConnector,
             cli_cmd_tx: ClientCommandSender,
             mut cli_evt_rx: ClientEventsReceiver,
         ) {
-            let mut conn_mock = init_connection(&conn_evt_tx, &mut cli_evt_rx).await;
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
             let mut notify_rx = cli_cmd_tx
                 .subscribe("path/to/resource", SIG_CHNG)
                 .expect("ClientCommand subscribe send");
@@ -799,11 +1269,11 @@ mod tests {
         }
 
         pub(super) async fn do_not_receive_unsubscribed_notification(
-            conn_evt_tx: Sender<ConnectionEvent>,
+            connector: MockConnector,
             cli_cmd_tx: ClientCommandSender,
             mut cli_evt_rx: ClientEventsReceiver,
         ) {
-            let mut conn_mock = init_connection(&conn_evt_tx, &mut cli_evt_rx).await;
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
             let mut notify_rx = cli_cmd_tx
                 .subscribe("path/to/resource", SIG_CHNG)
                 .expect("ClientCommand subscribe send");
@@ -824,11 +1294,11 @@ mod tests {
         }
 
         pub(super) async fn subscribe_and_unsubscribe(
-            conn_evt_tx: Sender<ConnectionEvent>,
+            connector: MockConnector,
             cli_cmd_tx: ClientCommandSender,
             mut cli_evt_rx: ClientEventsReceiver,
         ) {
-            let mut conn_mock = init_connection(&conn_evt_tx, &mut cli_evt_rx).await;
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
             let mut notify_rx_1 = cli_cmd_tx
                 .subscribe("path/to/resource", SIG_CHNG)
                 .expect("ClientCommand subscribe send");
@@ -912,6 +1382,19 @@ mod tests {
                                 PROPERTY_METHODS.iter(),
                                 [Route::new([crate::clientnode::METH_GET, crate::clientnode::METH_SET],
                                             RequestHandler::stateless(request_handler))]);
+            client.mount_fixed("silent",
+                                PROPERTY_METHODS.iter(),
+                                [Route::new([crate::clientnode::METH_GET, crate::clientnode::METH_SET],
+                                            RequestHandler::stateless(|_, _| async { }))]);
+            client.mount_fixed("panicking",
+                                PROPERTY_METHODS.iter(),
+                                [Route::new([crate::clientnode::METH_GET, crate::clientnode::METH_SET],
+                                            RequestHandler::stateless(|_, _| async { panic!("test panic") }))]);
+            client.mount("granted",
+                         ClientNode::fixed(PROPERTY_METHODS.iter(),
+                                           [Route::new([crate::clientnode::METH_GET, crate::clientnode::METH_SET],
+                                                       RequestHandler::stateless(request_handler))])
+                         .with_required_grant(crate::clientnode::METH_SET, "operator"));
             client
         }
 
@@ -920,11 +1403,11 @@ mod tests {
             conn_mock.expect_send_message().await
         }
 
-        pub(super) async fn handle_method_calls(conn_evt_tx: Sender<ConnectionEvent>,
+        pub(super) async fn handle_method_calls(connector: MockConnector,
                                          _cli_cmd_tx: ClientCommandSender,
                                          mut cli_evt_rx: ClientEventsReceiver)
         {
-            let mut conn_mock = init_connection(&conn_evt_tx, &mut cli_evt_rx).await;
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
 
             {
                 // Nonexisting method or path
@@ -949,6 +1432,19 @@ mod tests {
                 assert_eq!(response.code, RpcErrorCode::MethodNotFound);
             }
 
+            {
+                // SHV 3 discovery of the .app methods
+                let mut request = RpcMessage::new_request(".app", "dir", Some("date".into()));
+                request.set_access_level(AccessLevel::Browse);
+                let response = recv_request_get_response(&mut conn_mock, request).await;
+                assert!(response.result().expect("Response should be Ok").is_imap());
+
+                let mut request = RpcMessage::new_request(".app", "shvVersionMajor", None);
+                request.set_access_level(AccessLevel::Browse);
+                let response = recv_request_get_response(&mut conn_mock, request).await;
+                assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(3));
+            }
+
             {
                 // Access level is missing
                 let request = RpcMessage::new_request("dynamic/async", "dir", None);
@@ -974,10 +1470,15 @@ mod tests {
                 let response = recv_request_get_response(&mut conn_mock, request).await;
                 assert_eq!(response.result().expect("Response should be Ok").as_str(), "get");
 
+                // `chng` is listed as a signal of `get`, not as a method
                 let mut request = RpcMessage::new_request("dynamic/async", "dir", None);
                 request.set_access_level(AccessLevel::Browse);
                 let response = recv_request_get_response(&mut conn_mock, request).await;
-                assert_eq!(response.result().expect("Response should be Ok").as_list().len(), 5);
+                let methods = response.result().expect("Response should be Ok").as_list();
+                assert_eq!(methods.len(), 4);
+                let get = methods[2].as_imap();
+                assert_eq!(get.get(&1).map(RpcValue::as_str), Some(crate::clientnode::METH_GET));
+                assert_eq!(get.get(&6), Some(&RpcValue::from_cpon(r#"{"chng": null}"#).unwrap()));
             }
 
             {
@@ -997,6 +1498,605 @@ mod tests {
                 let response = recv_request_get_response(&mut conn_mock, request).await;
                 assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::PermissionDenied);
             }
+
+            {
+                // Access given as a string of grants
+                let mut request = RpcMessage::new_request("granted", "get", None);
+                request.set_access("rd,operator");
+                let response = recv_request_get_response(&mut conn_mock, request).await;
+                assert_eq!(response.result().expect("Response should be Ok").as_str(), "get");
+
+                let mut request = RpcMessage::new_request("granted", "set", None);
+                request.set_access("wr,operator");
+                let response = recv_request_get_response(&mut conn_mock, request).await;
+                assert_eq!(response.result().expect("Response should be Ok").as_str(), "set");
+
+                let mut request = RpcMessage::new_request("granted", "set", None);
+                request.set_access("su");
+                let response = recv_request_get_response(&mut conn_mock, request).await;
+                assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::PermissionDenied);
+
+                let mut request = RpcMessage::new_request("granted", "set", None);
+                request.set_access("rd,operator");
+                let response = recv_request_get_response(&mut conn_mock, request).await;
+                assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::PermissionDenied);
+            }
+
+            {
+                // Handler does not send any response
+                let mut request = RpcMessage::new_request("silent", "get", None);
+                request.set_access_level(AccessLevel::Read);
+                let response = recv_request_get_response(&mut conn_mock, request).await;
+                assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::MethodCallException);
+            }
+
+            {
+                // Handler panics
+                let mut request = RpcMessage::new_request("panicking", "get", None);
+                request.set_access_level(AccessLevel::Read);
+                let response = recv_request_get_response(&mut conn_mock, request).await;
+                assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::MethodCallException);
+                let ClientEvent::HandlerPanicked { path, method, message } = cli_evt_rx.wait_for_event().await.expect("Client event receive") else {
+                    panic!("Expected HandlerPanicked client event");
+                };
+                assert_eq!(path, "panicking");
+                assert_eq!(method, "get");
+                assert_eq!(message, "test panic");
+            }
+        }
+
+        pub(super) fn make_client_with_fixed_node() -> Client<()> {
+            let node = crate::fixed_node!{
+                fixed_handler(request, client_cmd_tx) {
+                    "value" [IsGetter, Read] -> i32 => {
+                        42
+                    }
+                    "unit" [None, Write] => {
+                    }
+                    "ok" [None, Write] -> bool => {
+                        Ok::<_, RpcError>(true)
+                    }
+                    "err" [None, Write] => {
+                        Err::<bool, _>(RpcError::new(RpcErrorCode::MethodCallException, "Failed"))
+                    }
+                    "deferred" [None, Write] => {
+                        let mut resp = request.prepare_response().unwrap();
+                        resp.set_result("deferred");
+                        client_cmd_tx.send_message(resp).unwrap();
+                        DeferredResponse
+                    }
+                    "setRange" [IsSetter, Write] (min: i32, max: i32, step: Option<i32>, wrap: bool = false) -> String => {
+                        format!("{min}..{max}, step: {step:?}, wrap: {wrap}")
+                    }
+                    "setLimit" [IsSetter, Write] (limit: Option<i32>) -> i32 => {
+                        limit.unwrap_or(-1)
+                    }
+                    "setCount" [IsSetter, Write] (count: i32 = 10) -> i32 => {
+                        count
+                    }
+                }
+            };
+            let mut client = Client::new(DotAppNode::new("test"));
+            client.mount("fixed", node);
+            client
+        }
+
+        async fn call_fixed(conn_mock: &mut ConnectionMock, method: &str, param: Option<RpcValue>) -> RpcMessage {
+            let mut request = RpcMessage::new_request("fixed", method, param);
+            request.set_access_level(AccessLevel::Write);
+            recv_request_get_response(conn_mock, request).await
+        }
+
+        pub(super) async fn handle_fixed_node_results(connector: MockConnector,
+                                                      _cli_cmd_tx: ClientCommandSender,
+                                                      mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+
+            let response = call_fixed(&mut conn_mock, "value", None).await;
+            assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(42));
+
+            let response = call_fixed(&mut conn_mock, "unit", None).await;
+            assert!(response.result().expect("Response should be Ok").is_null());
+
+            let response = call_fixed(&mut conn_mock, "ok", None).await;
+            assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(true));
+
+            let response = call_fixed(&mut conn_mock, "err", None).await;
+            let err = response.result().expect_err("Response should be Err");
+            assert_eq!(err.code, RpcErrorCode::MethodCallException);
+            assert_eq!(err.message, "Failed");
+
+            // The only response is the one sent by the method body
+            let response = call_fixed(&mut conn_mock, "deferred", None).await;
+            assert_eq!(response.result().expect("Response should be Ok").as_str(), "deferred");
+            let response = call_fixed(&mut conn_mock, "value", None).await;
+            assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(42));
+        }
+
+        pub(super) async fn handle_fixed_node_params(connector: MockConnector,
+                                                     _cli_cmd_tx: ClientCommandSender,
+                                                     mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+
+            {
+                // Several parameters given as a Map or a List
+                let param = RpcValue::from_cpon(r#"{"min": 1, "max": 5}"#).unwrap();
+                let response = call_fixed(&mut conn_mock, "setRange", Some(param)).await;
+                assert_eq!(response.result().expect("Response should be Ok").as_str(), "1..5, step: None, wrap: false");
+
+                let param = RpcValue::from_cpon("[1, 5, 2, true]").unwrap();
+                let response = call_fixed(&mut conn_mock, "setRange", Some(param)).await;
+                assert_eq!(response.result().expect("Response should be Ok").as_str(), "1..5, step: Some(2), wrap: true");
+
+                let param = RpcValue::from_cpon(r#"{"min": 1, "max": 5, "step": null, "wrap": true}"#).unwrap();
+                let response = call_fixed(&mut conn_mock, "setRange", Some(param)).await;
+                assert_eq!(response.result().expect("Response should be Ok").as_str(), "1..5, step: None, wrap: true");
+            }
+
+            {
+                // A single optional or defaulted parameter is the whole param
+                let response = call_fixed(&mut conn_mock, "setLimit", None).await;
+                assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(-1));
+
+                let response = call_fixed(&mut conn_mock, "setLimit", Some(3.into())).await;
+                assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(3));
+
+                let response = call_fixed(&mut conn_mock, "setCount", Some(RpcValue::null())).await;
+                assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(10));
+
+                let response = call_fixed(&mut conn_mock, "setCount", Some(3.into())).await;
+                assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(3));
+            }
+
+            {
+                // Invalid parameters
+                let param = RpcValue::from_cpon(r#"{"min": 1}"#).unwrap();
+                let err = call_fixed(&mut conn_mock, "setRange", Some(param)).await.result().expect_err("Response should be Err");
+                assert_eq!(err.code, RpcErrorCode::InvalidParam);
+                assert_eq!(err.message, "Missing field `max` in parameter for `setRange`");
+
+                let param = RpcValue::from_cpon(r#"{"min": "1", "max": 5}"#).unwrap();
+                let err = call_fixed(&mut conn_mock, "setRange", Some(param)).await.result().expect_err("Response should be Err");
+                assert_eq!(err.code, RpcErrorCode::InvalidParam);
+                assert!(err.message.starts_with("Wrong value of field `min` in parameter for `setRange`"));
+
+                let err = call_fixed(&mut conn_mock, "setRange", Some(1.into())).await.result().expect_err("Response should be Err");
+                assert_eq!(err.code, RpcErrorCode::InvalidParam);
+
+                let err = call_fixed(&mut conn_mock, "setLimit", Some("3".into())).await.result().expect_err("Response should be Err");
+                assert_eq!(err.code, RpcErrorCode::InvalidParam);
+            }
+
+            {
+                // Parameter types in the metadata
+                let mut request = RpcMessage::new_request("fixed", "dir", Some(true.into()));
+                request.set_access_level(AccessLevel::Browse);
+                let response = recv_request_get_response(&mut conn_mock, request).await;
+                let methods = response.result().expect("Response should be Ok").as_list();
+                let set_range = methods.iter()
+                    .map(RpcValue::as_map)
+                    .find(|method| method.get("name").map(RpcValue::as_str) == Some("setRange"))
+                    .expect("setRange in dir");
+                assert_eq!(set_range.get("param").map(RpcValue::as_str), Some("{min: Int, max: Int, step: Int|Null, wrap: Bool}"));
+                assert_eq!(set_range.get("result").map(RpcValue::as_str), Some("String"));
+            }
+        }
+
+        pub(super) fn make_client_with_properties() -> Client<()> {
+            let mut client = Client::new(DotAppNode::new("test"));
+            client.mount_property("property/rw", &PropertyNode::new(42));
+            client.mount_property("property/ro", &PropertyNode::read_only(String::from("foo")));
+            client
+        }
+
+        pub(super) async fn handle_property_requests(connector: MockConnector,
+                                                     _cli_cmd_tx: ClientCommandSender,
+                                                     mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+
+            let mut request = RpcMessage::new_request("property/rw", "get", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(42));
+
+            let mut request = RpcMessage::new_request("property/rw", "set", Some("bar".into()));
+            request.set_access_level(AccessLevel::Write);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::InvalidParam);
+
+            let mut request = RpcMessage::new_request("property/rw", "set", Some(43.into()));
+            request.set_access_level(AccessLevel::Write);
+            let signal = recv_request_get_response(&mut conn_mock, request).await;
+            assert!(signal.is_signal());
+            assert_eq!(signal.shv_path(), Some("property/rw"));
+            assert_eq!(signal.method(), Some(SIG_CHNG));
+            assert_eq!(signal.param(), Some(&RpcValue::from(43)));
+            let response = conn_mock.expect_send_message().await;
+            assert!(response.is_response());
+            response.result().expect("Response should be Ok");
+
+            let mut request = RpcMessage::new_request("property/ro", "get", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect("Response should be Ok").as_str(), "foo");
+
+            let mut request = RpcMessage::new_request("property/ro", "set", Some("bar".into()));
+            request.set_access_level(AccessLevel::Write);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::MethodNotFound);
+        }
+
+        pub(super) fn make_client_with_access_rules() -> Client<()> {
+            let mut client = make_client_with_properties();
+            client.with_access_rules(AccessRules::new()
+                                     .add_rule("property/**", "set", AccessLevel::Service)
+                                     .add_rule("property/ro", "*", AccessLevel::Service));
+            client
+        }
+
+        pub(super) async fn enforce_access_rules(connector: MockConnector,
+                                                 _cli_cmd_tx: ClientCommandSender,
+                                                 mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+
+            let mut request = RpcMessage::new_request("property/rw", "get", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(42));
+
+            let mut request = RpcMessage::new_request("property/rw", "set", Some(43.into()));
+            request.set_access_level(AccessLevel::Write);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::PermissionDenied);
+
+            let mut request = RpcMessage::new_request("property/rw", "set", Some(43.into()));
+            request.set_access_level(AccessLevel::Service);
+            let signal = recv_request_get_response(&mut conn_mock, request).await;
+            assert!(signal.is_signal());
+            let response = conn_mock.expect_send_message().await;
+            response.result().expect("Response should be Ok");
+
+            // Nodes hidden by the rules are not discoverable
+            let mut request = RpcMessage::new_request("property", "ls", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            let children = response.result().expect("Response should be Ok").as_list().iter().map(RpcValue::as_str).collect::<Vec<_>>();
+            assert_eq!(children, ["rw"]);
+
+            let mut request = RpcMessage::new_request("property", "ls", Some("ro".into()));
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect("Response should be Ok"), &RpcValue::from(false));
+
+            let mut request = RpcMessage::new_request("property/ro", "dir", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::PermissionDenied);
+
+            let mut request = RpcMessage::new_request("property", "ls", None);
+            request.set_access_level(AccessLevel::Service);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            let children = response.result().expect("Response should be Ok").as_list().iter().map(RpcValue::as_str).collect::<Vec<_>>();
+            assert_eq!(children, ["ro", "rw"]);
+        }
+
+        pub(super) fn make_client_with_device() -> Client<()> {
+            let device = DotDeviceNode::new("device", "1.0", Some("SN1".to_string()));
+            let alerts = device.alerts();
+            alerts.raise(Alert::new("overheat", 50));
+            let device = device.with_reset(move || {
+                let alerts = alerts.clone();
+                async move {
+                    alerts.clear_all();
+                    Ok(())
+                }
+            });
+            Client::new_device(DotAppNode::new("test"), device)
+        }
+
+        pub(super) async fn handle_device_requests(connector: MockConnector,
+                                                   _cli_cmd_tx: ClientCommandSender,
+                                                   mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+
+            let mut request = RpcMessage::new_request(".device", "uptime", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert!(response.result().expect("Response should be Ok").as_i64() >= 0);
+
+            let mut request = RpcMessage::new_request(".device", "alerts", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect("Response should be Ok").as_list().len(), 1);
+
+            let mut request = RpcMessage::new_request(".device", "reset", None);
+            request.set_access_level(AccessLevel::Write);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::PermissionDenied);
+
+            // The reset callback clears the alerts before the response is sent
+            let mut request = RpcMessage::new_request(".device", "reset", None);
+            request.set_access_level(AccessLevel::Service);
+            let signal = recv_request_get_response(&mut conn_mock, request).await;
+            assert!(signal.is_signal());
+            assert_eq!(signal.method(), Some("alertsmod"));
+            assert!(signal.param().expect("Signal should have a param").as_list().is_empty());
+            let response = conn_mock.expect_send_message().await;
+            assert!(response.result().expect("Response should be Ok").is_null());
+        }
+
+        pub(super) fn make_client_with_alarms() -> Client<()> {
+            let alarms = Alarms::new();
+            alarms.raise("temp", crate::AlarmSeverity::Warning, "High temperature");
+            let mut client = Client::new(DotAppNode::new("test"));
+            client.mount_alarms("alarms", &alarms);
+            client
+        }
+
+        pub(super) async fn announce_alarms_on_connect(connector: MockConnector,
+                                                       _cli_cmd_tx: ClientCommandSender,
+                                                       mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+
+            let signal = conn_mock.expect_send_message().await;
+            assert!(signal.is_signal());
+            assert_eq!(signal.shv_path(), Some("alarms"));
+            assert_eq!(signal.method(), Some(SIG_CHNG));
+            assert_eq!(signal.param().map(|alarms| alarms.as_list().len()), Some(1));
+
+            let mut request = RpcMessage::new_request("alarms", "ack", Some("temp".into()));
+            request.set_access_level(AccessLevel::Write);
+            let signal = recv_request_get_response(&mut conn_mock, request).await;
+            assert!(signal.is_signal());
+            let response = conn_mock.expect_send_message().await;
+            assert!(response.result().expect("Response should be Ok").as_bool());
+            drop(conn_mock);
+            expect_client_disconnected(&mut cli_evt_rx).await;
+
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+            let signal = conn_mock.expect_send_message().await;
+            assert_eq!(signal.shv_path(), Some("alarms"));
+            assert_eq!(signal.param().map(|alarms| alarms.as_list().len()), Some(1));
+        }
+
+        pub(super) fn make_client_with_journal() -> Client<()> {
+            let mut client = make_client_with_properties();
+            client.mount_journal("journal", &crate::Journal::new(10));
+            client
+        }
+
+        pub(super) async fn record_chng_in_journal(connector: MockConnector,
+                                                   _cli_cmd_tx: ClientCommandSender,
+                                                   mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+
+            let mut request = RpcMessage::new_request("property/rw", "set", Some(43.into()));
+            request.set_access_level(AccessLevel::Write);
+            let signal = recv_request_get_response(&mut conn_mock, request).await;
+            assert!(signal.is_signal());
+            conn_mock.expect_send_message().await.result().expect("Response should be Ok");
+
+            let param = RpcValue::from_cpon(r#"{"pathPattern": "property/**"}"#).unwrap();
+            let mut request = RpcMessage::new_request("journal", "getLog", Some(param));
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            let log = response.result().expect("Response should be Ok").as_list();
+            assert_eq!(log.len(), 1);
+            let [_, path, signal, source, value] = log[0].as_list().as_slice() else {
+                panic!("Invalid getLog record: {}", log[0].to_cpon());
+            };
+            assert_eq!(path.as_str(), "property/rw");
+            assert_eq!(signal.as_str(), SIG_CHNG);
+            assert_eq!(source.as_str(), "get");
+            assert_eq!(value, &RpcValue::from(43));
+        }
+
+        pub(super) fn make_client_with_offline_buffer() -> Client<()> {
+            let mut client = Client::new(DotAppNode::new("test"));
+            client.with_offline_buffer(crate::OfflineBufferOptions { capacity: 1, ..Default::default() });
+            client
+        }
+
+        pub(super) async fn flush_offline_buffer_on_connect(connector: MockConnector,
+                                                            cli_cmd_tx: ClientCommandSender,
+                                                            mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+            drop(conn_mock);
+            expect_client_disconnected(&mut cli_evt_rx).await;
+
+            for value in [1, 2] {
+                cli_cmd_tx.send_message(RpcMessage::new_signal("value", SIG_CHNG, Some(value.into()))).unwrap();
+            }
+            sync_client_commands(&cli_cmd_tx).await;
+
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+            let signal = conn_mock.expect_send_message().await;
+            assert!(signal.is_signal());
+            assert_eq!(signal.shv_path(), Some("value"));
+            assert_eq!(signal.param(), Some(&RpcValue::from(2)));
+        }
+
+        pub(super) fn make_client_with_request_buffer() -> Client<()> {
+            let mut client = Client::new(DotAppNode::new("test"));
+            client.with_offline_buffer(crate::OfflineBufferOptions { buffer_requests: true, ..Default::default() });
+            client
+        }
+
+        pub(super) async fn send_buffered_call_on_connect(connector: MockConnector,
+                                                          cli_cmd_tx: ClientCommandSender,
+                                                          mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+            drop(conn_mock);
+            expect_client_disconnected(&mut cli_evt_rx).await;
+
+            let mut resp_rx = cli_cmd_tx
+                .do_rpc_call("path/to/resource", "get")
+                .expect("RpcCall command send");
+            // The call is registered in the pending calls first, then its request
+            // is sent by another command queued behind the first sync
+            sync_client_commands(&cli_cmd_tx).await;
+            sync_client_commands(&cli_cmd_tx).await;
+
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+            let req = conn_mock.expect_send_message().await;
+            assert!(req.is_request());
+            assert_eq!(req.shv_path(), Some("path/to/resource"));
+            conn_mock.emulate_receive_response(&req, 42);
+
+            let resp = receive_rpc_msg(&mut resp_rx).await;
+            assert_eq!(resp.result().unwrap(), &RpcValue::from(42));
+        }
+
+        pub(super) fn make_client_with_alarms_and_offline_buffer() -> Client<()> {
+            let mut client = make_client_with_alarms();
+            client.with_offline_buffer(Default::default());
+            client
+        }
+
+        pub(super) async fn skip_buffered_chng_of_announced_alarms(connector: MockConnector,
+                                                                   cli_cmd_tx: ClientCommandSender,
+                                                                   mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+            assert_eq!(conn_mock.expect_send_message().await.shv_path(), Some("alarms"));
+            drop(conn_mock);
+            expect_client_disconnected(&mut cli_evt_rx).await;
+
+            cli_cmd_tx.send_message(RpcMessage::new_signal("alarms", SIG_CHNG, Some(Vec::<RpcValue>::new().into()))).unwrap();
+            cli_cmd_tx.send_message(RpcMessage::new_signal("value", SIG_CHNG, Some(1.into()))).unwrap();
+            sync_client_commands(&cli_cmd_tx).await;
+
+            // The buffered messages go first, the alarms only once by the announcement
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+            assert_eq!(conn_mock.expect_send_message().await.shv_path(), Some("value"));
+            let signal = conn_mock.expect_send_message().await;
+            assert_eq!(signal.shv_path(), Some("alarms"));
+            assert_eq!(signal.param().map(|alarms| alarms.as_list().len()), Some(1));
+
+            let mut request = RpcMessage::new_request(".app", "ping", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert!(response.is_response());
+        }
+
+        pub(super) fn make_client_with_custom_node() -> Client<()> {
+            struct Registers;
+
+            impl ShvNode<()> for Registers {
+                fn methods<'a>(&'a self, path: &'a str, _: Option<AppState<()>>) -> BoxFuture<'a, Option<Vec<&'a MetaMethod>>> {
+                    Box::pin(async move {
+                        match path {
+                            "" => Some(vec![]),
+                            "r1" | "r2" => Some(PROPERTY_METHODS.iter().collect()),
+                            _ => None,
+                        }
+                    })
+                }
+
+                fn children<'a>(&'a self, path: &'a str, _: Option<AppState<()>>) -> BoxFuture<'a, Option<Vec<String>>> {
+                    Box::pin(async move {
+                        match path {
+                            "" => Some(vec!["r1".into(), "r2".into()]),
+                            _ => Some(vec![]),
+                        }
+                    })
+                }
+
+                fn process_request<'a>(&'a self, ctx: RequestContext<()>) -> BoxFuture<'a, ()> {
+                    Box::pin(async move {
+                        match ctx.method().map(|mm| mm.name) {
+                            Some(crate::clientnode::METH_GET) => {
+                                let path = ctx.full_path();
+                                ctx.respond(path);
+                            }
+                            _ => ctx.respond_error(RpcError::new(RpcErrorCode::MethodCallException, "Not implemented")),
+                        }
+                    })
+                }
+            }
+
+            let mut client = Client::new(DotAppNode::new("test"));
+            client.mount_node("registers", Registers);
+            client
+        }
+
+        pub(super) async fn handle_custom_node_requests(connector: MockConnector,
+                                                        _cli_cmd_tx: ClientCommandSender,
+                                                        mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+
+            let mut request = RpcMessage::new_request("registers", "ls", None);
+            request.set_access_level(AccessLevel::Browse);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            let children = response.result().expect("Response should be Ok").as_list().iter().map(RpcValue::as_str).collect::<Vec<_>>();
+            assert_eq!(children, ["r1", "r2"]);
+
+            let mut request = RpcMessage::new_request("registers/r1", "dir", None);
+            request.set_access_level(AccessLevel::Browse);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect("Response should be Ok").as_list().len(), 4);
+
+            let mut request = RpcMessage::new_request("registers/r2", "get", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect("Response should be Ok").as_str(), "registers/r2");
+
+            let mut request = RpcMessage::new_request("registers/r2", "set", Some(1.into()));
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::PermissionDenied);
+
+            let mut request = RpcMessage::new_request("registers/r3", "get", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::MethodNotFound);
+        }
+
+        pub(super) fn make_client_with_middleware() -> Client<()> {
+            struct DenySet;
+
+            impl Middleware for DenySet {
+                fn process_request(&self, request: &RpcMessage) -> Option<Result<RpcValue, RpcError>> {
+                    if request.method() == Some(crate::clientnode::METH_SET) {
+                        Some(Err(RpcError::new(RpcErrorCode::MethodCallException, "Denied by middleware")))
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            let mut client = make_client_with_handlers();
+            client.add_middleware(DenySet);
+            client
+        }
+
+        pub(super) async fn middleware_short_circuits_request(connector: MockConnector,
+                                                              _cli_cmd_tx: ClientCommandSender,
+                                                              mut cli_evt_rx: ClientEventsReceiver)
+        {
+            let mut conn_mock = init_connection(&connector, &mut cli_evt_rx).await;
+
+            let mut request = RpcMessage::new_request("static", "set", None);
+            request.set_access_level(AccessLevel::Superuser);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            let err = response.result().expect_err("Response should be Err");
+            assert_eq!(err.code, RpcErrorCode::MethodCallException);
+            assert_eq!(err.message, "Denied by middleware");
+
+            let mut request = RpcMessage::new_request("static", "get", None);
+            request.set_access_level(AccessLevel::Read);
+            let response = recv_request_get_response(&mut conn_mock, request).await;
+            assert_eq!(response.result().expect("Response should be Ok").as_str(), "get");
         }
     }
 
@@ -1027,7 +2127,7 @@ mod tests {
     }
 
     generics_def!(TestDriverBounds <C, F, S> where
-                  C: FnOnce(Sender<ConnectionEvent>, ClientCommandSender, ClientEventsReceiver) -> F,
+                  C: FnOnce(MockConnector, ClientCommandSender, ClientEventsReceiver) -> F,
                   F: Future + Send + 'static,
                   F::Output: Send + 'static,
                   S: Sync + Send + 'static,
@@ -1051,13 +2151,12 @@ mod tests {
                     } else {
                         Client::new(DotAppNode::new("test"))
                     };
-                    let (conn_evt_tx, conn_evt_rx) = futures::channel::mpsc::unbounded::<ConnectionEvent>();
                     let (join_handle_tx, mut join_handle_rx) = futures::channel::mpsc::unbounded();
-                    let init_handler = move |cli_cmd_tx, cli_evt_rx| {
-                        let join_test_handle = ::tokio::task::spawn(test_drv(conn_evt_tx, cli_cmd_tx, cli_evt_rx));
+                    let init_handler = move |connector, cli_cmd_tx, cli_evt_rx| {
+                        let join_test_handle = ::tokio::task::spawn(test_drv(connector, cli_cmd_tx, cli_evt_rx));
                         join_handle_tx.unbounded_send(join_test_handle).unwrap();
                     };
-                    client.client_loop(conn_evt_rx, Some(init_handler)).await.expect("Client loop terminated with an error");
+                    client.run_with_mock(init_handler).await.expect("Client loop terminated with an error");
                     let join_handle = join_handle_rx.next().await.expect("fetch test join handle");
                     join_handle.await.expect("Test finished with error");
                 }
@@ -1087,13 +2186,12 @@ mod tests {
                     } else {
                         Client::new(DotAppNode::new("test"))
                     };
-                    let (conn_evt_tx, conn_evt_rx) = futures::channel::mpsc::unbounded::<ConnectionEvent>();
                     let (join_handle_tx, mut join_handle_rx) = futures::channel::mpsc::unbounded();
-                    let init_handler = move |cli_cmd_tx, cli_evt_rx| {
-                        let join_test_handle = ::async_std::task::spawn(test_drv(conn_evt_tx, cli_cmd_tx, cli_evt_rx));
+                    let init_handler = move |connector, cli_cmd_tx, cli_evt_rx| {
+                        let join_test_handle = ::async_std::task::spawn(test_drv(connector, cli_cmd_tx, cli_evt_rx));
                         join_handle_tx.unbounded_send(join_test_handle).unwrap();
                     };
-                    client.client_loop(conn_evt_rx, Some(init_handler)).await.expect("Client loop terminated with an error");
+                    client.run_with_mock(init_handler).await.expect("Client loop terminated with an error");
                     let join_handle = join_handle_rx.next().await.expect("fetch test join handle");
                     join_handle.await; //.expect("Test finished with error");
                 }
@@ -1108,7 +2206,76 @@ mod tests {
         };
     }
 
-    use drivers::make_client_with_handlers;
+    fn guarded_sender(request: &RpcMessage) -> (ClientCommandSender, Receiver<ClientCommand>) {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        (ClientCommandSender { sender, response_guard: None }.with_response_guard(request), receiver)
+    }
+
+    fn sent_message(receiver: &mut Receiver<ClientCommand>) -> Option<RpcMessage> {
+        match receiver.try_next() {
+            Ok(Some(ClientCommand::SendMessage { message })) => Some(message),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn response_guard_matches_caller_ids() {
+        let mut request = RpcMessage::new_request("test", "get", None);
+        request.set_caller_ids(&[1]);
+        let (client_cmd_tx, mut receiver) = guarded_sender(&request);
+
+        // The same request ID from another caller
+        let mut response = request.prepare_response().unwrap();
+        response.set_caller_ids(&[2]);
+        client_cmd_tx.send_message(response).unwrap();
+        assert!(!client_cmd_tx.response_sent());
+
+        client_cmd_tx.send_message(request.prepare_response().unwrap()).unwrap();
+        assert!(client_cmd_tx.response_sent());
+        drop(client_cmd_tx);
+        assert!(sent_message(&mut receiver).is_some());
+        assert!(sent_message(&mut receiver).is_some());
+        assert!(sent_message(&mut receiver).is_none());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn restart_dropped_client_loop() {
+        let mut client = Client::<()>::new(crate::appnodes::DotAppNode::new("test"));
+        ::tokio::runtime::Builder::new_multi_thread()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let (connector_tx, connector_rx) = std::sync::mpsc::channel();
+                {
+                    let run = client.run_with_mock(move |connector, _, _| connector_tx.send(connector).unwrap());
+                    futures::pin_mut!(run);
+                    assert!(futures::poll!(run).is_pending());
+                }
+                // The connection is still alive, but the loop future is gone
+                let _connector = connector_rx.recv().unwrap();
+                client
+                    .run_with_mock(|connector, _, _| drop(connector))
+                    .await
+                    .expect("Client loop should run again");
+            });
+    }
+
+    #[test]
+    fn response_guard_waits_for_sender_clones() {
+        let request = RpcMessage::new_request("test", "get", None);
+        let (client_cmd_tx, mut receiver) = guarded_sender(&request);
+        let kept_tx = client_cmd_tx.clone();
+        drop(client_cmd_tx);
+        assert!(sent_message(&mut receiver).is_none());
+
+        drop(kept_tx);
+        let response = sent_message(&mut receiver).expect("Error response should be sent");
+        assert_eq!(response.request_id(), request.request_id());
+        assert_eq!(response.result().expect_err("Response should be Err").code, RpcErrorCode::MethodCallException);
+    }
+
+    use drivers::{make_client_with_access_rules, make_client_with_alarms, make_client_with_custom_node, make_client_with_device, make_client_with_fixed_node, make_client_with_handlers, make_client_with_journal, make_client_with_middleware, make_client_with_offline_buffer, make_client_with_properties, make_client_with_request_buffer, make_client_with_alarms_and_offline_buffer};
 
     def_tests! {
         receive_connected_and_disconnected_events,
@@ -1119,7 +2286,19 @@ mod tests {
         receive_subscribed_notification,
         do_not_receive_unsubscribed_notification,
         subscribe_and_unsubscribe,
-        handle_method_calls (make_client_with_handlers())
+        handle_method_calls (make_client_with_handlers()),
+        handle_fixed_node_results (make_client_with_fixed_node()),
+        handle_fixed_node_params (make_client_with_fixed_node()),
+        middleware_short_circuits_request (make_client_with_middleware()),
+        handle_property_requests (make_client_with_properties()),
+        handle_custom_node_requests (make_client_with_custom_node()),
+        enforce_access_rules (make_client_with_access_rules()),
+        handle_device_requests (make_client_with_device()),
+        announce_alarms_on_connect (make_client_with_alarms()),
+        record_chng_in_journal (make_client_with_journal()),
+        flush_offline_buffer_on_connect (make_client_with_offline_buffer()),
+        send_buffered_call_on_connect (make_client_with_request_buffer()),
+        skip_buffered_chng_of_announced_alarms (make_client_with_alarms_and_offline_buffer())
     }
 
 }
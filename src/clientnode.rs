@@ -1,20 +1,27 @@
 // The file originates from https://github.com/silicon-heaven/shv-rs/blob/e740fd301dc65f3412ad1154595bf61ee5632aba/src/shvnode.rs
 // struct ShvNode has been adapted to support async process_request accepting RpcCommand channel and a shared state params
 
-use crate::client::{RequestHandler, ClientCommandSender, MethodsGetter, AppState};
+use crate::acl::AccessRules;
+use crate::client::{RequestHandler, RequestContext, ChildrenGetter, ClientCommand, ClientCommandSender, MethodsGetter, AppState, Client};
 use crate::runtime::spawn_task;
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use log::{error, debug};
 use shvrpc::rpcframe::RpcFrame;
-use shvrpc::{metamethod, RpcMessage, RpcMessageMetaTags};
+use shvrpc::metamethod;
 use shvproto::rpcvalue;
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::any::Any;
 use std::format;
-use std::rc::Rc;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
 // Reexport for use in the macros
 pub use shvrpc::metamethod::{AccessLevel, Flag, MetaMethod};
 pub use shvrpc::rpcmessage::{RpcError, RpcErrorCode};
+pub use shvrpc::{RpcMessage, RpcMessageMetaTags};
 pub use shvproto::{RpcValue, Value};
+#[doc(hidden)]
+pub use crate::typename::{shv_type_name, ShvTypeName};
 
 enum DirParam {
     Brief,
@@ -38,20 +45,63 @@ impl From<Option<&RpcValue>> for DirParam {
     }
 }
 
-fn dir<'a>(methods: impl IntoIterator<Item = &'a MetaMethod>, param: DirParam) -> RpcValue {
+const DIR_SIGNALS_KEY: i32 = 6;
+const DIR_SIGNALS_NAME: &str = "signals";
+
+pub(crate) fn is_signal(method: &MetaMethod) -> bool {
+    method.flags & (Flag::IsSignal as u32) != 0u32
+}
+
+// method -> signals attached to it
+pub(crate) type MethodSignals = BTreeMap<String, Vec<String>>;
+
+fn method_to_rpcvalue(mm: &MetaMethod, signals: Option<&Vec<String>>, full: bool) -> RpcValue {
+    let format = if full { metamethod::DirFormat::Map } else { metamethod::DirFormat::IMap };
+    let value = mm.to_rpcvalue(format);
+    let Some(signals) = signals.filter(|signals| !signals.is_empty()) else {
+        return value;
+    };
+    // A null signal type means the type of the method result
+    let signals: rpcvalue::Map = signals
+        .iter()
+        .map(|sig| (sig.clone(), RpcValue::null()))
+        .collect();
+    if full {
+        let mut map = value.as_map().clone();
+        map.insert(DIR_SIGNALS_NAME.into(), signals.into());
+        map.into()
+    } else {
+        let mut imap = value.as_imap().clone();
+        imap.insert(DIR_SIGNALS_KEY, signals.into());
+        imap.into()
+    }
+}
+
+fn dir<'a>(methods: impl IntoIterator<Item = &'a MetaMethod>, signals: &MethodSignals, param: DirParam) -> RpcValue {
+    // Signals given as `IsSignal` rows are not methods, they are listed
+    // with `get`, which is the default source of the SHV signals
+    let (signal_rows, methods): (Vec<_>, Vec<_>) = methods.into_iter().partition(|mm| is_signal(mm));
+    let mut signals = std::borrow::Cow::Borrowed(signals);
+    for row in signal_rows {
+        let get_signals = signals.to_mut().entry(METH_GET.to_string()).or_default();
+        if !get_signals.iter().any(|signal| signal == row.name) {
+            get_signals.push(row.name.to_string());
+        }
+    }
     let mut result = RpcValue::null();
     let mut lst = rpcvalue::List::new();
     for mm in methods {
+        let method_signals = signals.get(mm.name);
         match param {
             DirParam::Brief => {
-                lst.push(mm.to_rpcvalue(metamethod::DirFormat::IMap));
+                lst.push(method_to_rpcvalue(mm, method_signals, false));
             }
             DirParam::Full => {
-                lst.push(mm.to_rpcvalue(metamethod::DirFormat::Map));
+                lst.push(method_to_rpcvalue(mm, method_signals, true));
             }
             DirParam::BriefMethod(ref method_name) => {
                 if mm.name == method_name {
-                    result = mm.to_rpcvalue(metamethod::DirFormat::IMap);
+                    result = method_to_rpcvalue(mm, method_signals, false);
                     break;
                 }
             }
@@ -92,6 +142,7 @@ pub(crate) enum RequestResult {
 pub(crate) fn process_local_dir_ls<V>(
     mounts: &BTreeMap<String, V>,
     frame: &RpcFrame,
+    access_rules: &AccessRules,
 ) -> Option<RequestResult> {
     let method = frame.method().unwrap_or_default();
     if !(method == METH_DIR || method == METH_LS) {
@@ -112,32 +163,40 @@ pub(crate) fn process_local_dir_ls<V>(
             format!("Invalid shv path: {}", shv_path),
         )));
     }
+    let Ok(rpcmsg) = frame.to_rpcmesage() else {
+        return Some(RequestResult::Error(RpcError::new(
+            RpcErrorCode::InvalidRequest,
+            "Cannot convert RPC frame to RPC message".to_string(),
+        )));
+    };
+    let rq_level = RequestAccess::from_request(&rpcmsg).level();
+    if !access_rules.allows(shv_path, method, rq_level) {
+        return Some(RequestResult::Error(RpcError::new(
+            RpcErrorCode::PermissionDenied,
+            format!("Insufficient permissions. Method '{shv_path}:{method}()' is denied by access rules"),
+        )));
+    }
     if method == METH_DIR && !is_mount_point {
         // dir in the middle of the tree must be resolved locally
-        if let Ok(rpcmsg) = frame.to_rpcmesage() {
-            let dir = dir(DIR_LS_METHODS.iter(), rpcmsg.param().into());
-            return Some(RequestResult::Response(dir));
-        } else {
-            return Some(RequestResult::Error(RpcError::new(
-                RpcErrorCode::InvalidRequest,
-                "Cannot convert RPC frame to RPC message".to_string(),
-            )));
-        }
+        let dir = dir(DIR_LS_METHODS.iter(), &MethodSignals::new(), rpcmsg.param().into());
+        return Some(RequestResult::Response(dir));
     }
     if method == METH_LS && !is_leaf {
         // ls on not-leaf node must be resolved locally
-        if let Ok(rpcmsg) = frame.to_rpcmesage() {
-            let ls = ls_children_to_result(children_on_path, rpcmsg.param().into());
-            return Some(ls);
-        } else {
-            return Some(RequestResult::Error(RpcError::new(
-                RpcErrorCode::InvalidRequest,
-                "Cannot convert RPC frame to RPC message".to_string(),
-            )));
-        }
+        let children = children_on_path.map(|children| visible_children(children, shv_path, access_rules, rq_level));
+        return Some(ls_children_to_result(children, rpcmsg.param().into()));
     }
     None
 }
+
+// Leaves out the children that the access rules do not allow to list
+fn visible_children(children: Vec<String>, path: &str, access_rules: &AccessRules, rq_level: Option<i32>) -> Vec<String> {
+    children
+        .into_iter()
+        .filter(|child| access_rules.allows(&join_path(path, child), METH_LS, rq_level))
+        .collect()
+}
+
 fn ls_children_to_result(children: Option<Vec<String>>, param: LsParam) -> RequestResult {
     match param {
         LsParam::List => match children {
@@ -243,7 +302,29 @@ impl<T> Route<T> {
     }
 }
 
-type StaticNodeHandlers<T> = BTreeMap<String, Rc<RequestHandler<T>>>;
+/// A node that can be mounted on a `Client`
+///
+/// The library resolves `dir`, the access levels of the requests and `ls`
+/// (if `children()` returns `Some`) and passes the other requests to
+/// `process_request()`. All the methods take a path relative to the mount
+/// point of the node.
+pub trait ShvNode<T>: Send + Sync {
+    /// Returns the methods of the node on `path` except for `dir` and `ls`,
+    /// or `None` if the path does not exist.
+    fn methods<'a>(&'a self, path: &'a str, app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<&'a MetaMethod>>>;
+
+    /// Returns the children of the node on `path` to be listed by `ls`.
+    /// `None` means that `ls` is handled by `process_request()`.
+    fn children<'a>(&'a self, _path: &'a str, _app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<String>>> {
+        Box::pin(async { None })
+    }
+
+    /// Processes a request to a method from `methods()`. The node is
+    /// responsible for sending a response.
+    fn process_request<'a>(&'a self, ctx: RequestContext<T>) -> BoxFuture<'a, ()>;
+}
+
+type StaticNodeHandlers<T> = BTreeMap<String, Arc<RequestHandler<T>>>;
 
 struct FixedNode<'a, T> {
     methods: Vec<&'a MetaMethod>,
@@ -252,8 +333,9 @@ struct FixedNode<'a, T> {
 
 impl<'a, T> FixedNode<'a, T> {
     fn new(methods: impl IntoIterator<Item = &'a MetaMethod>, routes: impl IntoIterator<Item = Route<T>>) -> Self {
-        let methods = DIR_LS_METHODS.iter().chain(methods).collect::<Vec<&MetaMethod>>();
-        let handlers = Self::add_routes(&methods, routes);
+        let methods = methods.into_iter().collect::<Vec<&MetaMethod>>();
+        let all_methods = DIR_LS_METHODS.iter().chain(methods.iter().copied()).collect::<Vec<&MetaMethod>>();
+        let handlers = Self::add_routes(&all_methods, routes);
         Self {
             methods,
             handlers,
@@ -265,14 +347,11 @@ impl<'a, T> FixedNode<'a, T> {
             panic!("Duplicate method '{}' in a static node definition", dup_method.name);
         }
         let mut handlers: StaticNodeHandlers<T> = Default::default();
-        fn is_signal(method: &MetaMethod) -> bool {
-            method.flags & (Flag::IsSignal as u32) != 0u32
-        }
         for route in routes {
             if route.methods.iter().any(|m| m == METH_DIR) {
                 panic!("Custom implementation of 'dir', which is handled by the library");
             }
-            let handler = Rc::new(route.handler);
+            let handler = Arc::new(route.handler);
             route.methods.iter().for_each(|m| {
                 methods
                     .iter()
@@ -291,126 +370,316 @@ impl<'a, T> FixedNode<'a, T> {
     }
 }
 
+impl<'m, T> ShvNode<T> for FixedNode<'m, T> {
+    fn methods<'a>(&'a self, path: &'a str, _app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<&'a MetaMethod>>> {
+        // Static nodes do not have any own children. Any child nodes are
+        // resolved on the mounts tree level in `process_local_dir_ls()`.
+        let methods = path.is_empty().then(|| self.methods.clone());
+        Box::pin(async move { methods })
+    }
+
+    fn children<'a>(&'a self, _path: &'a str, _app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<String>>> {
+        let children = (!self.handlers.contains_key(METH_LS)).then(Vec::new);
+        Box::pin(async move { children })
+    }
+
+    fn process_request<'a>(&'a self, ctx: RequestContext<T>) -> BoxFuture<'a, ()> {
+        match ctx.request().method().and_then(|method| self.handlers.get(method)) {
+            Some(handler) => handler.0(ctx),
+            None => Box::pin(async move {
+                let method = ctx.request().method().unwrap_or_default().to_owned();
+                ctx.respond_error(RpcError::new(
+                        RpcErrorCode::MethodNotFound,
+                        format!("No handler for method: {method}")));
+            }),
+        }
+    }
+}
+
 struct DynamicNode<T> {
     methods: MethodsGetter<T>,
+    children: Option<ChildrenGetter<T>>,
     handler: RequestHandler<T>,
 }
 
+impl<T> ShvNode<T> for DynamicNode<T> {
+    fn methods<'a>(&'a self, path: &'a str, app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<&'a MetaMethod>>> {
+        Box::pin(self.methods.0(path.to_owned(), app_state).map(|methods| methods.map(|m| m.into_iter().collect())))
+    }
+
+    fn children<'a>(&'a self, path: &'a str, app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<String>>> {
+        match &self.children {
+            Some(children) => children.0(path.to_owned(), app_state),
+            None => Box::pin(async { None }),
+        }
+    }
+
+    fn process_request<'a>(&'a self, ctx: RequestContext<T>) -> BoxFuture<'a, ()> {
+        self.handler.0(ctx)
+    }
+}
+
 pub trait ConstantNode {
     fn methods(&self) -> Vec<&MetaMethod>;
     fn process_request(&self, request: &RpcMessage) -> Option<Result<RpcValue, RpcError>>;
 }
 
-// NOTE: Implementing Steady and Dynamic nodes using async trait would allow to
-// remove Constant variant. Steady node would have only one handler for the whole node.
+impl<T, N> ShvNode<T> for N
+where
+    N: ConstantNode + Send + Sync,
+{
+    fn methods<'a>(&'a self, path: &'a str, _app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<&'a MetaMethod>>> {
+        // Constant nodes do not have any own children
+        let methods = path.is_empty().then(|| ConstantNode::methods(self));
+        Box::pin(async move { methods })
+    }
 
-enum NodeVariant<'a, T> {
-    Fixed(FixedNode<'a, T>),
-    Dynamic(Arc<DynamicNode<T>>),
-    Constant(Box<dyn ConstantNode>),
+    fn children<'a>(&'a self, _path: &'a str, _app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<String>>> {
+        Box::pin(async { Some(Vec::new()) })
+    }
+
+    fn process_request<'a>(&'a self, ctx: RequestContext<T>) -> BoxFuture<'a, ()> {
+        let result = ConstantNode::process_request(self, ctx.request()).unwrap_or_else(|| Err(RpcError::new(
+                    RpcErrorCode::MethodNotFound,
+                    format!("Invalid method: {:?}", ctx.request().method()))));
+        ctx.respond_result(result);
+        Box::pin(async { })
+    }
 }
 
-pub struct ClientNode<'a, T>(NodeVariant<'a, T>);
+pub struct ClientNode<'a, T> {
+    node: Arc<dyn ShvNode<T> + 'a>,
+    // method -> custom grant required in addition to the access level
+    required_grants: Arc<BTreeMap<String, String>>,
+    signals: Arc<MethodSignals>,
+}
 
 impl<'a, T: Sync + Send + 'static> ClientNode<'a, T> {
+    pub fn new(node: impl ShvNode<T> + 'a) -> Self {
+        Self {
+            node: Arc::new(node),
+            required_grants: Default::default(),
+            signals: Default::default(),
+        }
+    }
+
+    /// Requires the caller to have a custom grant, e.g. `operator`,
+    /// to call `method`, in addition to the access level of the method.
+    pub fn with_required_grant(mut self, method: impl Into<String>, grant: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.required_grants).insert(method.into(), grant.into());
+        self
+    }
+
+    /// Attaches signals to the methods of the node, given as pairs
+    /// `(method, signal)`, e.g. `[("get", "chng")]`. The signals are listed
+    /// with their methods by `dir`.
+    pub fn with_signals(mut self, signals: &[(&str, &str)]) -> Self {
+        let node_signals = Arc::make_mut(&mut self.signals);
+        for (method, signal) in signals {
+            node_signals.entry(method.to_string()).or_default().push(signal.to_string());
+        }
+        self
+    }
+
     pub fn fixed(methods: impl IntoIterator<Item = &'a MetaMethod>, routes: impl IntoIterator<Item = Route<T>>) -> Self {
-        Self(NodeVariant::Fixed(FixedNode::new(methods, routes)))
+        Self::new(FixedNode::new(methods, routes))
     }
 
     pub fn dynamic(methods: MethodsGetter<T>, handler: RequestHandler<T>) -> Self {
-        Self(NodeVariant::Dynamic(Arc::new(DynamicNode { methods, handler })))
+        Self::new(DynamicNode { methods, children: None, handler })
+    }
+
+    /// A dynamic node, which answers `ls` by the children getter. The request
+    /// handler does not need to handle `ls` then.
+    pub fn dynamic_with_children(methods: MethodsGetter<T>, children: ChildrenGetter<T>, handler: RequestHandler<T>) -> Self {
+        Self::new(DynamicNode { methods, children: Some(children), handler })
     }
 
     // NOTE: Not included in the public API. Constant nodes are meant
     // for implementation of special nodes like .app and .device and
-    // should not be needed outside of the library.
+    // should not be needed outside of the library. Custom nodes can be
+    // mounted by `ClientNode::new()` either way.
     pub(crate) fn constant<N>(node: N) -> Self
     where
-        N: ConstantNode + 'static,
+        N: ConstantNode + Send + Sync + 'static,
     {
-        Self(NodeVariant::Constant(Box::new(node)))
+        Self::new(node)
     }
+}
 
-    pub(crate) async fn process_request(&self, request: RpcMessage, mount_path: String, client_cmd_tx: ClientCommandSender, app_state: &Option<AppState<T>>) {
-        match &self.0 {
-            NodeVariant::Fixed(node) => {
-                let methods = if request.shv_path().unwrap_or_default().is_empty() {
-                    node.methods.as_slice()
-                } else {
-                    // Static nodes do not have any own children. Any child nodes are
-                    // resolved on the mounts tree level in `process_local_dir_ls()`.
-                    &[]
-                };
-                if resolve_request_access(&request, &mount_path, &client_cmd_tx, methods) {
-                    let Some(method) = request.method() else {
-                        panic!("BUG: Request method should be Some after access check.");
-                    };
-                    if method == self::METH_DIR {
-                        let result = dir(methods.iter().copied(), request.param().into());
-                        send_response(request, client_cmd_tx, Ok(result));
-                    } else if let Some(handler) = node.handlers.get(method) {
-                        spawn_task(handler.0(request, client_cmd_tx, app_state.clone()));
-                    } else if method == self::METH_LS {
-                        let result = default_ls(request.param());
-                        send_response(request, client_cmd_tx, Ok(result));
-                    } else {
-                        panic!("BUG: Unhandled method '{mount_path}:{method}()' should have been caught in the node constructor");
-                    }
+/// A subtree of nodes that can be mounted on a `Client` at once
+///
+/// Usually implemented by `#[derive(ShvNode)]` (feature `macros`).
+/// Mounting consumes the value and returns handles of the mounted nodes.
+pub trait NodeTree {
+    type Nodes;
+    fn mount_tree<T: Send + Sync + 'static>(self, client: &mut Client<T>, path: &str) -> Self::Nodes;
+}
+
+impl<T: Sync + Send + 'static> ClientNode<'static, T> {
+    pub(crate) fn process_request(
+        &self,
+        request: RpcMessage,
+        mount_path: String,
+        client_cmd_tx: ClientCommandSender,
+        app_state: &Option<AppState<T>>,
+        access_rules: &Arc<AccessRules>,
+    ) {
+        let node = self.node.clone();
+        let access_rules = access_rules.clone();
+        let required_grants = self.required_grants.clone();
+        let signals = self.signals.clone();
+        let app_state = app_state.clone();
+        let client_cmd_tx = client_cmd_tx.with_response_guard(&request);
+        let panic_cmd_tx = client_cmd_tx.clone();
+        let panic_response = request.prepare_response();
+        let full_path = join_path(&mount_path, request.shv_path().unwrap_or_default());
+        let method_name = request.method().unwrap_or_default().to_owned();
+        let process = async move {
+            let shv_path = request.shv_path().unwrap_or_default().to_owned();
+            let methods = node.methods(&shv_path, app_state.clone()).await
+                .map_or_else(
+                    Vec::new,
+                    |m| DIR_LS_METHODS.iter().chain(m).collect());
+            let access = RequestAccess::from_request(&request);
+            if !resolve_request_access(&request, &access, &mount_path, &client_cmd_tx, &methods, &required_grants, &access_rules) {
+                return;
+            }
+            let method = methods.iter().find(|mm| Some(mm.name) == request.method() && !is_signal(mm)).copied();
+            match request.method() {
+                Some(self::METH_DIR) => {
+                    let result = dir(methods, &signals, request.param().into());
+                    send_response(request, client_cmd_tx, Ok(result));
+                    return;
                 }
-            },
-            NodeVariant::Dynamic(node) => {
-                let app_state = app_state.clone();
-                let shv_path = request.shv_path().unwrap_or_default().to_owned();
-                let node = node.clone();
-                spawn_task(async move {
-                    let methods = node.methods.0(shv_path, app_state.clone()).await
-                        .map_or_else(
-                            Vec::new,
-                            |m| DIR_LS_METHODS.iter().chain(m).collect());
-                    if resolve_request_access(&request, &mount_path, &client_cmd_tx, &methods) {
-                        match request.method() {
-                            Some(self::METH_DIR) => {
-                                let result = dir(methods.into_iter(), request.param().into());
-                                send_response(request, client_cmd_tx, Ok(result));
-                            }
-                            Some(_) =>
-                                node.handler.0(request, client_cmd_tx, app_state).await,
-                            _ =>
-                                panic!("BUG: Request method should be Some after access check."),
+                Some(self::METH_LS) => {
+                    if let Some(children) = node.children(&shv_path, app_state.clone()).await {
+                        let path = join_path(&mount_path, &shv_path);
+                        let children = visible_children(children, &path, &access_rules, access.level());
+                        let result = match ls_children_to_result(Some(children), request.param().into()) {
+                            RequestResult::Response(res) => Ok(res),
+                            RequestResult::Error(err) => Err(err),
                         };
-                    }
-                });
-            },
-            NodeVariant::Constant(node) => {
-                let methods = if request.shv_path().unwrap_or_default().is_empty() {
-                    DIR_LS_METHODS.iter().chain(node.methods()).collect()
-                } else {
-                    // Static nodes do not have any own children. Any child nodes are
-                    // resolved on the mounts tree level in `process_local_dir_ls()`.
-                    vec![]
-                };
-                if resolve_request_access(&request, &mount_path, &client_cmd_tx, &methods) {
-                    let Some(method) = request.method() else {
-                        panic!("BUG: Request method should be Some after access check.");
-                    };
-                    if method == self::METH_DIR {
-                        let result = dir(methods.iter().copied(), request.param().into());
-                        send_response(request, client_cmd_tx, Ok(result));
-                    } else if let Some(result) = node.process_request(&request) {
                         send_response(request, client_cmd_tx, result);
-                    } else if method == self::METH_LS {
-                        let result = default_ls(request.param());
-                        send_response(request, client_cmd_tx, Ok(result));
-                    } else {
-                        panic!("BUG: Unhandled method '{mount_path}:{method}()' should have been caught in the node constructor");
+                        return;
+                    }
+                }
+                _ => { }
+            }
+            let ctx = RequestContext::new(request, access, client_cmd_tx, app_state, mount_path, method);
+            node.process_request(ctx).await;
+        };
+        spawn_task(async move {
+            let Err(panic) = AssertUnwindSafe(process).catch_unwind().await else {
+                return;
+            };
+            let message = panic_message(panic.as_ref());
+            error!("Request handler of {full_path}:{method_name}() panicked: {message}");
+            if !panic_cmd_tx.response_sent() {
+                if let Ok(mut response) = panic_response {
+                    response.set_error(RpcError::new(
+                            RpcErrorCode::MethodCallException,
+                            format!("Method handler panicked: {message}")));
+                    if let Err(err) = panic_cmd_tx.send_message(response) {
+                        error!("Cannot send response. Error: {err}");
                     }
                 }
-            },
+            }
+            if let Err(err) = panic_cmd_tx.sender.unbounded_send(ClientCommand::HandlerPanicked { path: full_path, method: method_name, message }) {
+                error!("Cannot report a panic of a request handler. Error: {err}");
+            }
+        });
+    }
+}
+
+/// Access of a request
+///
+/// SHV 3 brokers send the access as a string of comma separated grants,
+/// e.g. `rd,wr,srv`. The grants naming an access level (`bws`, `rd`, `wr`,
+/// `cmd`, `cfg`, `srv`, `ssrv`, `dev`, `su`) determine the access level,
+/// which is the highest of them. Any other grants are custom roles.
+/// The numeric access level of a request takes precedence over the one
+/// parsed from the access string.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RequestAccess {
+    level: Option<i32>,
+    grants: Vec<String>,
+}
+
+const ACCESS_LEVEL_GRANTS: [(&str, i32); 9] = [
+    ("bws", AccessLevel::Browse as i32),
+    ("rd", AccessLevel::Read as i32),
+    ("wr", AccessLevel::Write as i32),
+    ("cmd", AccessLevel::Command as i32),
+    ("cfg", AccessLevel::Config as i32),
+    ("srv", AccessLevel::Service as i32),
+    ("ssrv", AccessLevel::SuperService as i32),
+    ("dev", AccessLevel::Devel as i32),
+    ("su", AccessLevel::Superuser as i32),
+];
+
+impl RequestAccess {
+    pub fn parse(access: &str) -> Self {
+        let mut level = None;
+        let mut grants = Vec::new();
+        for grant in access.split(',').map(str::trim).filter(|grant| !grant.is_empty()) {
+            match ACCESS_LEVEL_GRANTS.iter().find(|(name, _)| *name == grant) {
+                Some((_, grant_level)) => level = level.max(Some(*grant_level)),
+                None => grants.push(grant.to_string()),
+            }
         }
+        Self { level, grants }
+    }
+
+    pub fn from_request(request: &RpcMessage) -> Self {
+        let mut access = request.access().map(Self::parse).unwrap_or_default();
+        if let Some(level) = request.access_level() {
+            access.level = Some(level);
+        }
+        access
+    }
+
+    pub fn level(&self) -> Option<i32> {
+        self.level
+    }
+
+    /// Custom grants, not including the access level ones
+    pub fn grants(&self) -> &[String] {
+        &self.grants
+    }
+
+    pub fn has_grant(&self, grant: &str) -> bool {
+        self.grants.iter().any(|g| g == grant)
     }
 }
 
-fn resolve_request_access(request: &RpcMessage, mount_path: &String, client_cmd_tx: &ClientCommandSender, methods: &[&MetaMethod]) -> bool {
+pub(crate) fn join_path(mount_path: &str, path: &str) -> String {
+    if path.is_empty() {
+        mount_path.to_owned()
+    } else {
+        format!("{mount_path}/{path}")
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+fn resolve_request_access(
+    request: &RpcMessage,
+    access: &RequestAccess,
+    mount_path: &String,
+    client_cmd_tx: &ClientCommandSender,
+    methods: &[&MetaMethod],
+    required_grants: &BTreeMap<String, String>,
+    access_rules: &AccessRules,
+) -> bool {
 
     let shv_path = request.shv_path().unwrap_or_default();
     let check_request_access = || {
@@ -420,17 +689,15 @@ fn resolve_request_access(request: &RpcMessage, mount_path: &String, client_cmd_
         } else {
             &format!("{mount_path}/{shv_path}")
         };
-        let Some(mm) = methods.iter().find(|mm| mm.name == method) else {
+        let Some(mm) = methods.iter().find(|mm| mm.name == method && !is_signal(mm)) else {
             return Err(RpcError::new(RpcErrorCode::MethodNotFound,
                                      format!("Unknown method on path '{full_path}:{method}()'")));
         };
-        let Some(rq_level) = request.access_level() else {
+        let Some(rq_level) = access.level() else {
             return Err(RpcError::new(RpcErrorCode::InvalidRequest, "Undefined access level"));
         };
-        if rq_level >= mm.access as i32 {
-            Ok(())
-        } else {
-            Err(RpcError::new(
+        if rq_level < mm.access as i32 {
+            return Err(RpcError::new(
                     RpcErrorCode::PermissionDenied,
                     format!("Insufficient permissions. \
                             Method '{full_path}:{method}()' \
@@ -440,7 +707,22 @@ fn resolve_request_access(request: &RpcMessage, mount_path: &String, client_cmd_
                             mm.access,
                             )
                     )
-               )
+               );
+        }
+        if let Some(rule_level) = access_rules.required_access(full_path, method).filter(|level| rq_level < *level) {
+            return Err(RpcError::new(
+                    RpcErrorCode::PermissionDenied,
+                    format!("Insufficient permissions. \
+                            Method '{full_path}:{method}()' \
+                            called with access level {rq_level:?}, required {rule_level} by access rules")));
+        }
+        match required_grants.get(method) {
+            Some(grant) if !access.has_grant(grant) => Err(RpcError::new(
+                    RpcErrorCode::PermissionDenied,
+                    format!("Insufficient permissions. \
+                            Method '{full_path}:{method}()' \
+                            requires grant '{grant}'"))),
+            _ => Ok(()),
         }
     };
 
@@ -458,6 +740,75 @@ fn resolve_request_access(request: &RpcMessage, mount_path: &String, client_cmd_
     false
 }
 
+/// Marks a method whose response is sent later by the method body itself
+///
+/// The request gets an error response if the `ClientCommandSender` and all
+/// its clones are dropped without sending the response.
+pub struct DeferredResponse;
+
+/// Conversion of method bodies in `fixed_node!` to a response
+///
+/// `None` means that no response should be sent by the generated code.
+/// Implement it for custom types to return them directly from the bodies.
+pub trait IntoMethodResult {
+    fn into_method_result(self) -> Option<Result<RpcValue, RpcError>>;
+}
+
+impl IntoMethodResult for Option<Result<RpcValue, RpcError>> {
+    fn into_method_result(self) -> Option<Result<RpcValue, RpcError>> {
+        self
+    }
+}
+
+impl IntoMethodResult for DeferredResponse {
+    fn into_method_result(self) -> Option<Result<RpcValue, RpcError>> {
+        None
+    }
+}
+
+impl<T, E> IntoMethodResult for Result<T, E>
+where
+    T: Into<RpcValue>,
+    E: Into<RpcError>,
+{
+    fn into_method_result(self) -> Option<Result<RpcValue, RpcError>> {
+        Some(self.map(Into::into).map_err(Into::into))
+    }
+}
+
+impl<T> IntoMethodResult for Vec<T>
+where
+    Vec<T>: Into<RpcValue>,
+{
+    fn into_method_result(self) -> Option<Result<RpcValue, RpcError>> {
+        Some(Ok(self.into()))
+    }
+}
+
+macro_rules! impl_into_method_result {
+    ($($type:ty),+) => {
+        $(impl IntoMethodResult for $type {
+            fn into_method_result(self) -> Option<Result<RpcValue, RpcError>> {
+                Some(Ok(self.into()))
+            }
+        })+
+    };
+}
+
+impl_into_method_result!(RpcValue, (), bool, i32, i64, u32, u64, f64, &str, String, rpcvalue::Map);
+
+/// Returns a field of a request param given either as a Map or a List.
+/// Null fields are treated as missing.
+pub fn param_field<'a>(param: Option<&'a RpcValue>, name: &str, index: usize) -> Option<&'a RpcValue> {
+    let param = param?;
+    let field = if param.is_map() {
+        param.as_map().get(name)
+    } else {
+        param.as_list().get(index)
+    };
+    field.filter(|value| !value.is_null())
+}
+
 pub fn send_response(request: RpcMessage, client_cmd_tx: ClientCommandSender, result: Result<RpcValue, RpcError>) {
     match request.prepare_response() {
         Err(err) => {
@@ -508,6 +859,8 @@ pub(crate) const DIR_LS_METHODS: [MetaMethod; 2] = [
         description: "",
     },
 ];
+
+/// Methods of a property node, `dir` lists the `chng` row as a signal of `get`
 pub const PROPERTY_METHODS: [MetaMethod; 3] = [
     MetaMethod {
         name: METH_GET,
@@ -618,4 +971,55 @@ mod tests {
         let duplicate_methods = PROPERTY_METHODS.iter().chain(DIR_LS_METHODS.iter());
         ClientNode::fixed(duplicate_methods, vec![Route::new([METH_GET, METH_SET, METH_LS], RequestHandler::stateful(dummy_handler))]);
     }
+    #[test]
+    fn parse_request_access() {
+        let access = RequestAccess::parse("rd,wr,operator");
+        assert_eq!(access.level(), Some(AccessLevel::Write as i32));
+        assert_eq!(access.grants(), ["operator"]);
+        assert!(access.has_grant("operator"));
+        assert!(!access.has_grant("wr"));
+
+        let access = RequestAccess::parse("su");
+        assert_eq!(access.level(), Some(AccessLevel::Superuser as i32));
+        assert!(access.grants().is_empty());
+
+        let access = RequestAccess::parse("admin");
+        assert_eq!(access.level(), None);
+
+        assert_eq!(RequestAccess::parse(""), RequestAccess::default());
+    }
+
+    #[test]
+    fn dir_attaches_signals_to_methods() {
+        let signals = MethodSignals::from([(METH_GET.to_string(), vec![SIG_CHNG.to_string()])]);
+        // The signals do not depend on the order of the methods
+        let methods = [&PROPERTY_METHODS[1], &PROPERTY_METHODS[0]];
+        let result = dir(DIR_LS_METHODS.iter().chain(methods), &signals, DirParam::Brief);
+        let result = result.as_list();
+        assert_eq!(result.len(), 4);
+        assert!(!result[2].as_imap().contains_key(&DIR_SIGNALS_KEY));
+        let get = result[3].as_imap();
+        assert_eq!(get.get(&1).map(RpcValue::as_str), Some(METH_GET));
+        let get_signals = get.get(&DIR_SIGNALS_KEY).expect("signals of `get`").as_map();
+        assert_eq!(get_signals.get(SIG_CHNG), Some(&RpcValue::null()));
+
+        let result = dir(methods, &signals, DirParam::Full);
+        let get = result.as_list()[1].as_map();
+        assert!(get.get(DIR_SIGNALS_NAME).expect("signals of `get`").as_map().contains_key(SIG_CHNG));
+
+        let result = dir(methods, &signals, DirParam::BriefMethod(METH_GET.into()));
+        assert!(result.as_imap().contains_key(&DIR_SIGNALS_KEY));
+
+        // The `IsSignal` rows are not listed as methods, the signals are attached to `get`
+        let result = dir(PROPERTY_METHODS.iter(), &MethodSignals::new(), DirParam::Brief);
+        let result = result.as_list();
+        assert_eq!(result.len(), 2);
+        assert!(result.iter().all(|mm| mm.as_imap().get(&1).map(RpcValue::as_str) != Some(SIG_CHNG)));
+        let get_signals = result[0].as_imap().get(&DIR_SIGNALS_KEY).expect("signals of `get`").as_map();
+        assert_eq!(get_signals.len(), 1);
+        assert_eq!(get_signals.get(SIG_CHNG), Some(&RpcValue::null()));
+
+        let result = dir(PROPERTY_METHODS.iter(), &signals, DirParam::BriefMethod(SIG_CHNG.into()));
+        assert!(result.as_list().is_empty());
+    }
 }
@@ -11,28 +11,76 @@ use shvrpc::framerw::{FrameReader, FrameWriter};
 use shvrpc::rpcframe::RpcFrame;
 use shvrpc::util::login_from_url;
 use shvrpc::{client, RpcMessage};
+use std::sync::Arc;
+use std::time::SystemTime;
 use url::Url;
 
-pub fn spawn_connection_task(config: &ClientConfig, conn_evt_tx: Sender<ConnectionEvent>) {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameDirection {
+    Received,
+    Sent,
+}
+
+/// A frame passing through the connection, as seen by a `FrameObserver`
+///
+/// `size` is the size of the frame payload in bytes.
+pub struct FrameTrace<'a> {
+    pub direction: FrameDirection,
+    pub timestamp: SystemTime,
+    pub size: usize,
+    pub frame: &'a RpcFrame,
+}
+
+/// Observer of all the frames received and sent by the connection task
+///
+/// The observer is called synchronously from the connection task,
+/// so it should not block for a long time.
+pub trait FrameObserver: Send + Sync {
+    fn observe(&self, trace: &FrameTrace);
+}
+
+impl<F> FrameObserver for F
+where
+    F: Fn(&FrameTrace) + Send + Sync,
+{
+    fn observe(&self, trace: &FrameTrace) {
+        self(trace)
+    }
+}
+
+pub(crate) type FrameObserverRef = Arc<dyn FrameObserver>;
+
+fn observe_frame(observer: &Option<FrameObserverRef>, direction: FrameDirection, frame: &RpcFrame) {
+    if let Some(observer) = observer {
+        observer.observe(&FrameTrace {
+            direction,
+            timestamp: SystemTime::now(),
+            size: frame.data.len(),
+            frame,
+        });
+    }
+}
+
+pub fn spawn_connection_task(config: &ClientConfig, conn_evt_tx: Sender<ConnectionEvent>, frame_observer: Option<FrameObserverRef>) {
     match current_task_runtime() {
         #[cfg(feature = "tokio")]
-        Runtime::Tokio => tokio::spawn_connection_task(config, conn_evt_tx),
+        Runtime::Tokio => tokio::spawn_connection_task(config, conn_evt_tx, frame_observer),
         #[cfg(feature = "async_std")]
-        Runtime::AsyncStd => async_std::spawn_connection_task(config, conn_evt_tx),
+        Runtime::AsyncStd => async_std::spawn_connection_task(config, conn_evt_tx, frame_observer),
         _ => panic!("Could not find suitable async runtime"),
     };
 }
 
 #[cfg(feature = "tokio")]
 mod tokio {
-    use super::{connection_task, ClientConfig, ConnectionEvent, Sender};
+    use super::{connection_task, ClientConfig, ConnectionEvent, FrameObserverRef, Sender};
     use tokio::io::BufReader;
     use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
     use tokio::net::TcpStream;
     use tokio_util::compat::{Compat, TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 
-    pub fn spawn_connection_task(config: &ClientConfig, conn_evt_tx: Sender<ConnectionEvent>) {
-        tokio::spawn(connection_task(config.clone(), conn_evt_tx, connect));
+    pub fn spawn_connection_task(config: &ClientConfig, conn_evt_tx: Sender<ConnectionEvent>, frame_observer: Option<FrameObserverRef>) {
+        tokio::spawn(connection_task(config.clone(), conn_evt_tx, frame_observer, connect));
     }
 
     async fn connect(
@@ -49,7 +97,7 @@ mod tokio {
 
 #[cfg(feature = "async_std")]
 mod async_std {
-    use super::{connection_task, ClientConfig, ConnectionEvent, Sender};
+    use super::{connection_task, ClientConfig, ConnectionEvent, FrameObserverRef, Sender};
     use futures::io::{BufReader, ReadHalf, WriteHalf};
     use futures::AsyncReadExt;
     use async_std::net::TcpStream;
@@ -57,8 +105,9 @@ mod async_std {
     pub(super) fn spawn_connection_task(
         config: &ClientConfig,
         conn_evt_tx: Sender<ConnectionEvent>,
+        frame_observer: Option<FrameObserverRef>,
     ) {
-        async_std::task::spawn(connection_task(config.clone(), conn_evt_tx, connect));
+        async_std::task::spawn(connection_task(config.clone(), conn_evt_tx, frame_observer, connect));
     }
 
     async fn connect(
@@ -90,7 +139,12 @@ generics_def!(
 );
 
 #[generics(ConnectBounds)]
-async fn connection_task<C>(config: ClientConfig, conn_event_sender: Sender<ConnectionEvent>, connect: C) -> shvrpc::Result<()>
+async fn connection_task<C>(
+    config: ClientConfig,
+    conn_event_sender: Sender<ConnectionEvent>,
+    frame_observer: Option<FrameObserverRef>,
+    connect: C,
+) -> shvrpc::Result<()>
 where
     C: FnOnce(String) -> F + Clone,
 {
@@ -100,7 +154,7 @@ where
                 Ok(interval) => {
                     info!("Reconnect interval set to: {:?}", interval);
                     loop {
-                        match connection_loop(&config, &conn_event_sender, connect.clone()).await {
+                        match connection_loop(&config, &conn_event_sender, &frame_observer, connect.clone()).await {
                             Ok(_) => {
                                 return Ok(());
                             }
@@ -117,7 +171,7 @@ where
                 }
             }
         } else {
-            connection_loop(&config, &conn_event_sender, connect).await
+            connection_loop(&config, &conn_event_sender, &frame_observer, connect).await
         }
     }
     .await;
@@ -135,6 +189,7 @@ where
 async fn connection_loop<C>(
     config: &ClientConfig,
     conn_event_sender: &Sender<ConnectionEvent>,
+    frame_observer: &Option<FrameObserverRef>,
     connect: C,
 ) -> shvrpc::Result<()>
 where
@@ -194,7 +249,13 @@ where
                                 ConnectionCommand::SendMessage(message) => {
                                     // reset heartbeat timer
                                     fut_heartbeat_timeout = futures_time::task::sleep(heartbeat_interval.into()).fuse();
-                                    frame_writer.send_message(message).await?;
+                                    if frame_observer.is_some() {
+                                        let frame = message.to_frame()?;
+                                        observe_frame(frame_observer, FrameDirection::Sent, &frame);
+                                        frame_writer.send_frame(frame).await?;
+                                    } else {
+                                        frame_writer.send_message(message).await?;
+                                    }
                                 },
                             }
                         },
@@ -207,6 +268,7 @@ where
                 receive_frame_result = fut_receive_frame => {
                     match receive_frame_result {
                         Ok(frame) => {
+                            observe_frame(frame_observer, FrameDirection::Received, &frame);
                             conn_event_sender.unbounded_send(ConnectionEvent::RpcFrameReceived(frame))?;
                         }
                         Err(e) => {
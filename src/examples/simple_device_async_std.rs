@@ -131,6 +131,9 @@ async fn emit_chng_task(
                     emit_signal = false;
                     warn!("Device disconnected");
                 },
+                Ok(ClientEvent::HandlerPanicked { path, method, message }) => {
+                    error!("Handler of {path}:{method}() panicked: {message}");
+                },
                 Err(err) => {
                     error!("Device event error: {err}");
                     return Ok(());
@@ -168,7 +171,13 @@ pub(crate) async fn main() -> shvrpc::Result<()> {
         async_std::task::spawn(emit_chng_task(client_cmd_tx, client_evt_rx, counter));
     };
 
-    shvclient::Client::new_device(DotAppNode::new("simple_device_async_std"), DotDeviceNode::new("simple_device", "0.1", Some("00000".into())))
+    let device_node = DotDeviceNode::new("simple_device", "0.1", Some("00000".into()))
+        .with_reset(|| async {
+            warn!("Device reset requested");
+            Ok(())
+        });
+
+    shvclient::Client::new_device(DotAppNode::new("simple_device_async_std"), device_node)
         .mount("status/delayed", ClientNode::fixed(
                 &DELAY_METHODS,
                 [Route::new(
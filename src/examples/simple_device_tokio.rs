@@ -8,7 +8,7 @@ use shvrpc::metamethod::MetaMethod;
 use shvrpc::{client::ClientConfig, util::parse_log_verbosity};
 use shvrpc::{RpcMessage, RpcMessageMetaTags};
 use shvclient::{MethodsGetter, RequestHandler};
-use shvclient::clientnode::{ClientNode, PROPERTY_METHODS, SIG_CHNG};
+use shvclient::clientnode::{ClientNode, DeferredResponse, PROPERTY_METHODS, SIG_CHNG};
 use shvclient::{ClientCommandSender, ClientEvent, ClientEventsReceiver, AppState};
 use simple_logger::SimpleLogger;
 use shvproto::{RpcValue, TryFromRpcValue};
@@ -90,6 +90,9 @@ async fn emit_chng_task(
                     emit_signal = false;
                     warn!("Device disconnected");
                 },
+                Ok(ClientEvent::HandlerPanicked { path, method, message }) => {
+                    error!("Handler of {path}:{method}() panicked: {message}");
+                },
                 Err(err) => {
                     error!("Device event error: {err}");
                     return Ok(());
@@ -173,8 +176,8 @@ pub(crate) async fn main() -> shvrpc::Result<()> {
                 println!("param data: {:?}", &param);
                 Some(Ok(().into()))
             }
-            "42" [IsGetter, Browse] => {
-                Some(Ok(RpcValue::from(42)))
+            "42" [IsGetter, Browse, description: "The answer"] -> i32 => {
+                42
             }
         }
     };
@@ -201,15 +204,15 @@ pub(crate) async fn main() -> shvrpc::Result<()> {
 
                 // The response is sent in the task above, so we need
                 // to tell the library to not send any response.
-                None
+                DeferredResponse
 
-                // Otherwise, return either RpcValue or RpcError
-                // Some(Ok(shvrpc::RpcValue::from(true)))
+                // Otherwise, return a value or a Result
+                // Ok::<_, RpcError>(true)
             }
         }
     );
 
-    shvclient::Client::new(DotAppNode::new("simple_device_tokio"))
+    shvclient::Client::new(DotAppNode::new("simple_device_tokio").with_version(env!("CARGO_PKG_VERSION")))
         .mount("stateless", stateless_node)
         .mount("status/delayed", delay_node)
         .mount("status/dyn", ClientNode::dynamic(
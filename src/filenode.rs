@@ -0,0 +1,559 @@
+use crate::client::{AppState, ChildrenGetter, MethodsGetter, RequestContext, RequestHandler};
+use crate::clientnode::{ClientNode, ShvNode};
+use crate::runtime::spawn_blocking;
+use futures::future::BoxFuture;
+use sha1::{Digest, Sha1};
+use shvrpc::metamethod::{AccessLevel, Flag, MetaMethod};
+use shvrpc::rpcmessage::{RpcError, RpcErrorCode};
+use shvrpc::{RpcMessage, RpcMessageMetaTags};
+use shvproto::{rpcvalue, DateTime, RpcValue};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const METH_STAT: &str = "stat";
+pub const METH_SIZE: &str = "size";
+pub const METH_CRC: &str = "crc";
+pub const METH_SHA1: &str = "sha1";
+pub const METH_READ: &str = "read";
+pub const METH_WRITE: &str = "write";
+pub const METH_TRUNCATE: &str = "truncate";
+
+const DEFAULT_MAX_READ_SIZE: usize = 32 * 1024;
+const DEFAULT_MAX_WRITE_SIZE: usize = 32 * 1024;
+
+// Keys of the `stat` result
+const STAT_TYPE: i32 = 0;
+const STAT_SIZE: i32 = 1;
+const STAT_PAGE_SIZE: i32 = 2;
+const STAT_MOD_TIME: i32 = 4;
+const STAT_MAX_WRITE: i32 = 5;
+
+const FILE_TYPE_REGULAR: i32 = 0;
+
+/// Storage of a `FileNode`
+///
+/// Write operations are not supported by default.
+pub trait FileBackend: Send + Sync {
+    fn size(&self) -> io::Result<u64>;
+
+    fn modified(&self) -> io::Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
+    /// Reads at most `size` bytes starting at `offset`
+    fn read(&self, offset: u64, size: usize) -> io::Result<Vec<u8>>;
+
+    fn write(&self, _offset: u64, _data: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn truncate(&self, _size: u64) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// A file on the local filesystem
+pub struct LocalFile {
+    path: PathBuf,
+}
+
+impl LocalFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl FileBackend for LocalFile {
+    fn size(&self) -> io::Result<u64> {
+        Ok(std::fs::metadata(&self.path)?.len())
+    }
+
+    fn modified(&self) -> io::Result<Option<SystemTime>> {
+        Ok(std::fs::metadata(&self.path)?.modified().ok())
+    }
+
+    fn read(&self, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::with_capacity(size);
+        file.take(size as u64).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)
+    }
+
+    fn truncate(&self, size: u64) -> io::Result<()> {
+        OpenOptions::new().write(true).open(&self.path)?.set_len(size)
+    }
+}
+
+const fn file_method(name: &'static str, flags: Flag, access: AccessLevel, param: &'static str, result: &'static str) -> MetaMethod {
+    MetaMethod {
+        name,
+        flags: flags as u32,
+        access,
+        param,
+        result,
+        description: "",
+    }
+}
+
+const FILE_READ_METHODS: [MetaMethod; 5] = [
+    file_method(METH_STAT, Flag::None, AccessLevel::Read, "", "Stat"),
+    file_method(METH_SIZE, Flag::IsGetter, AccessLevel::Read, "", "Int"),
+    file_method(METH_CRC, Flag::None, AccessLevel::Read, "[Int, Int]|Null", "UInt"),
+    file_method(METH_SHA1, Flag::None, AccessLevel::Read, "[Int, Int]|Null", "Blob"),
+    file_method(METH_READ, Flag::None, AccessLevel::Read, "[Int, Int]", "Blob"),
+];
+
+const FILE_WRITE_METHODS: [MetaMethod; 2] = [
+    file_method(METH_WRITE, Flag::None, AccessLevel::Write, "[Int, Blob]", ""),
+    file_method(METH_TRUNCATE, Flag::None, AccessLevel::Write, "Int", ""),
+];
+
+/// A node implementing the SHV file interface
+///
+/// The node answers `stat`, `size`, `crc`, `sha1` and `read` and, unless
+/// it is read-only, `write` and `truncate`. `read` returns at most
+/// the maximal read size bytes, the callers are expected to read larger
+/// files in chunks. `write` accepts at most the maximal write size bytes.
+/// The file operations run on the blocking threads of the runtime.
+/// Mount it by `Client::mount_node()`.
+pub struct FileNode {
+    file: Arc<FileAccess>,
+    methods: Vec<MetaMethod>,
+}
+
+// The file operations of a `FileNode`, shared with the blocking tasks
+struct FileAccess {
+    backend: Box<dyn FileBackend>,
+    max_read_size: usize,
+    // `None` if the file is read-only
+    max_write_size: Option<usize>,
+}
+
+impl FileNode {
+    pub fn new(backend: impl FileBackend + 'static) -> Self {
+        let mut node = Self::read_only(backend);
+        node.methods.extend(FILE_WRITE_METHODS);
+        node.file_mut().max_write_size = Some(DEFAULT_MAX_WRITE_SIZE);
+        node
+    }
+
+    pub fn read_only(backend: impl FileBackend + 'static) -> Self {
+        Self {
+            file: Arc::new(FileAccess {
+                backend: Box::new(backend),
+                max_read_size: DEFAULT_MAX_READ_SIZE,
+                max_write_size: None,
+            }),
+            methods: FILE_READ_METHODS.into(),
+        }
+    }
+
+    /// Sets the access level of the reading methods
+    pub fn with_read_access(self, access: AccessLevel) -> Self {
+        self.with_access(&[METH_STAT, METH_SIZE, METH_CRC, METH_SHA1, METH_READ], access)
+    }
+
+    /// Sets the access level of `write` and `truncate`
+    pub fn with_write_access(self, access: AccessLevel) -> Self {
+        self.with_access(&[METH_WRITE, METH_TRUNCATE], access)
+    }
+
+    pub fn with_max_read_size(mut self, max_read_size: usize) -> Self {
+        self.file_mut().max_read_size = max_read_size;
+        self
+    }
+
+    /// Sets the maximal size of data accepted by `write`, ignored if the node
+    /// is read-only
+    pub fn with_max_write_size(mut self, max_write_size: usize) -> Self {
+        let file = self.file_mut();
+        if file.max_write_size.is_some() {
+            file.max_write_size = Some(max_write_size);
+        }
+        self
+    }
+
+    fn with_access(mut self, methods: &[&str], access: AccessLevel) -> Self {
+        self.methods
+            .iter_mut()
+            .filter(|mm| methods.contains(&mm.name))
+            .for_each(|mm| mm.access = access);
+        self
+    }
+
+    fn file_mut(&mut self) -> &mut FileAccess {
+        Arc::get_mut(&mut self.file).expect("The file is not shared while the node is being built")
+    }
+
+    fn check_method(&self, request: &RpcMessage) -> Result<(), RpcError> {
+        let method = request.method().unwrap_or_default();
+        if self.methods.iter().any(|mm| mm.name == method) {
+            Ok(())
+        } else {
+            Err(RpcError::new(RpcErrorCode::MethodNotFound, format!("Invalid method: {method}")))
+        }
+    }
+
+    fn process(&self, request: &RpcMessage) -> Result<RpcValue, RpcError> {
+        self.check_method(request)?;
+        self.file.process(request)
+    }
+}
+
+impl FileAccess {
+    fn stat(&self) -> io::Result<RpcValue> {
+        let mut stat = rpcvalue::IMap::new();
+        stat.insert(STAT_TYPE, FILE_TYPE_REGULAR.into());
+        stat.insert(STAT_SIZE, (self.backend.size()? as i64).into());
+        stat.insert(STAT_PAGE_SIZE, (self.max_read_size as i64).into());
+        if let Some(modified) = self.backend.modified()? {
+            let msec = modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64);
+            stat.insert(STAT_MOD_TIME, DateTime::from_epoch_msec(msec).into());
+        }
+        if let Some(max_write_size) = self.max_write_size {
+            stat.insert(STAT_MAX_WRITE, (max_write_size as i64).into());
+        }
+        Ok(stat.into())
+    }
+
+    /// Passes the data in the range to `f` in chunks of the maximal read size
+    fn for_each_chunk(&self, offset: u64, size: u64, mut f: impl FnMut(&[u8])) -> io::Result<()> {
+        let mut pos = offset;
+        let end = offset.saturating_add(size);
+        while pos < end {
+            let chunk_size = (end - pos).min(self.max_read_size as u64) as usize;
+            let chunk = self.backend.read(pos, chunk_size)?;
+            if chunk.is_empty() {
+                break;
+            }
+            f(&chunk);
+            pos += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn range_param(&self, param: Option<&RpcValue>, method: &str) -> Result<(u64, u64), RpcError> {
+        match param {
+            None => Ok((0, self.backend.size().map_err(io_error)?)),
+            Some(param) if param.is_null() => Ok((0, self.backend.size().map_err(io_error)?)),
+            Some(param) => match param.as_list().as_slice() {
+                [offset, size] if offset.is_int() && size.is_int() && offset.as_int() >= 0 && size.as_int() >= 0 =>
+                    Ok((offset.as_int() as u64, size.as_int() as u64)),
+                _ => Err(RpcError::new(
+                        RpcErrorCode::InvalidParam,
+                        format!("Wrong parameter for `{method}`, expected [offset, size]"))),
+            },
+        }
+    }
+
+    fn process(&self, request: &RpcMessage) -> Result<RpcValue, RpcError> {
+        let method = request.method().unwrap_or_default();
+        match method {
+            METH_STAT => self.stat().map_err(io_error),
+            METH_SIZE => self.backend.size().map(|size| (size as i64).into()).map_err(io_error),
+            METH_CRC => {
+                let (offset, size) = self.range_param(request.param(), method)?;
+                let mut hasher = crc32fast::Hasher::new();
+                self.for_each_chunk(offset, size, |chunk| hasher.update(chunk)).map_err(io_error)?;
+                Ok((hasher.finalize() as u64).into())
+            }
+            METH_SHA1 => {
+                let (offset, size) = self.range_param(request.param(), method)?;
+                let mut hasher = Sha1::new();
+                self.for_each_chunk(offset, size, |chunk| hasher.update(chunk)).map_err(io_error)?;
+                Ok(hasher.finalize().to_vec().into())
+            }
+            METH_READ => {
+                let Some(param) = request.param().filter(|param| !param.is_null()) else {
+                    return Err(RpcError::new(RpcErrorCode::InvalidParam, format!("Missing parameter for `{method}`")));
+                };
+                let (offset, size) = self.range_param(Some(param), method)?;
+                let size = size.min(self.max_read_size as u64) as usize;
+                self.backend.read(offset, size).map(RpcValue::from).map_err(io_error)
+            }
+            METH_WRITE => {
+                let Some([offset, data]) = request.param().map(|param| param.as_list().as_slice()) else {
+                    return Err(RpcError::new(RpcErrorCode::InvalidParam, format!("Wrong parameter for `{method}`, expected [offset, data]")));
+                };
+                if !offset.is_int() || offset.as_int() < 0 || !data.is_blob() {
+                    return Err(RpcError::new(RpcErrorCode::InvalidParam, format!("Wrong parameter for `{method}`, expected [offset, data]")));
+                }
+                let max_write_size = self.max_write_size.unwrap_or_default();
+                if data.as_blob().len() > max_write_size {
+                    return Err(RpcError::new(
+                            RpcErrorCode::InvalidParam,
+                            format!("Data for `{method}` exceed the maximal write size {max_write_size}")));
+                }
+                self.backend.write(offset.as_int() as u64, data.as_blob()).map(|_| RpcValue::null()).map_err(io_error)
+            }
+            METH_TRUNCATE => match request.param() {
+                Some(size) if size.is_int() && size.as_int() >= 0 =>
+                    self.backend.truncate(size.as_int() as u64).map(|_| RpcValue::null()).map_err(io_error),
+                _ => Err(RpcError::new(RpcErrorCode::InvalidParam, format!("Wrong parameter for `{method}`, expected size"))),
+            },
+            _ => Err(RpcError::new(RpcErrorCode::MethodNotFound, format!("Invalid method: {method}"))),
+        }
+    }
+}
+
+fn io_error(err: io::Error) -> RpcError {
+    RpcError::new(RpcErrorCode::MethodCallException, format!("File operation failed: {err}"))
+}
+
+impl<T> ShvNode<T> for FileNode {
+    fn methods<'a>(&'a self, path: &'a str, _app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<&'a MetaMethod>>> {
+        let methods = path.is_empty().then(|| self.methods.iter().collect());
+        Box::pin(async move { methods })
+    }
+
+    fn children<'a>(&'a self, _path: &'a str, _app_state: Option<AppState<T>>) -> BoxFuture<'a, Option<Vec<String>>> {
+        Box::pin(async { Some(Vec::new()) })
+    }
+
+    fn process_request<'a>(&'a self, ctx: RequestContext<T>) -> BoxFuture<'a, ()> {
+        if let Err(err) = self.check_method(ctx.request()) {
+            ctx.respond_error(err);
+            return Box::pin(async { });
+        }
+        let file = self.file.clone();
+        Box::pin(async move {
+            let request = ctx.request().clone();
+            let result = spawn_blocking(move || file.process(&request)).await;
+            ctx.respond_result(result);
+        })
+    }
+}
+
+/// A local directory exposed as a subtree of file nodes
+///
+/// `ls` lists the entries of the directories and every regular file is
+/// a read-only `FileNode` unless the node is made writable. Paths leading
+/// outside the root directory, including by symbolic links, are refused.
+/// The filesystem operations run on the blocking threads of the runtime.
+/// Mount it by `Client::mount()` with `DirectoryNode::client_node()`.
+#[derive(Clone)]
+pub struct DirectoryNode {
+    root: PathBuf,
+    writable: bool,
+    max_read_size: usize,
+}
+
+impl DirectoryNode {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            writable: false,
+            max_read_size: DEFAULT_MAX_READ_SIZE,
+        }
+    }
+
+    /// Enables `write` and `truncate` of the files
+    pub fn writable(mut self) -> Self {
+        self.writable = true;
+        self
+    }
+
+    pub fn with_max_read_size(mut self, max_read_size: usize) -> Self {
+        self.max_read_size = max_read_size;
+        self
+    }
+
+    /// Resolves a path relative to the root, refusing anything outside
+    /// of the root directory
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.root.clone();
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            if segment == "." || segment == ".." || segment.contains('\\') {
+                return None;
+            }
+            resolved.push(segment);
+        }
+        let root = self.root.canonicalize().ok()?;
+        let resolved = resolved.canonicalize().ok()?;
+        resolved.starts_with(&root).then_some(resolved)
+    }
+
+    fn methods(&self, path: &str) -> Option<Vec<&'static MetaMethod>> {
+        let path = self.resolve(path)?;
+        if path.is_dir() {
+            Some(Vec::new())
+        } else if path.is_file() {
+            let write_methods: &'static [MetaMethod] = if self.writable { &FILE_WRITE_METHODS } else { &[] };
+            Some(FILE_READ_METHODS.iter().chain(write_methods).collect())
+        } else {
+            None
+        }
+    }
+
+    fn children(&self, path: &str) -> Option<Vec<String>> {
+        let path = self.resolve(path)?;
+        if path.is_file() {
+            return Some(Vec::new());
+        }
+        let mut children = std::fs::read_dir(path)
+            .ok()?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect::<Vec<_>>();
+        children.sort();
+        Some(children)
+    }
+
+    fn process(&self, request: &RpcMessage) -> Result<RpcValue, RpcError> {
+        let path = request.shv_path().unwrap_or_default();
+        let Some(file_path) = self.resolve(path).filter(|file_path| file_path.is_file()) else {
+            return Err(RpcError::new(RpcErrorCode::MethodNotFound, format!("Invalid file path: {path}")));
+        };
+        let file = LocalFile::new(file_path);
+        let node = if self.writable { FileNode::new(file) } else { FileNode::read_only(file) };
+        node.with_max_read_size(self.max_read_size).process(request)
+    }
+
+    pub fn client_node<T: Send + Sync + 'static>(self) -> ClientNode<'static, T> {
+        let dir = Arc::new(self);
+        let methods_dir = dir.clone();
+        let children_dir = dir.clone();
+        ClientNode::dynamic_with_children(
+            MethodsGetter::new(move |path, _| {
+                let dir = methods_dir.clone();
+                spawn_blocking(move || dir.methods(&path))
+            }),
+            ChildrenGetter::new(move |path, _| {
+                let dir = children_dir.clone();
+                spawn_blocking(move || dir.children(&path))
+            }),
+            RequestHandler::with_context(move |ctx: RequestContext<T>| {
+                let dir = dir.clone();
+                async move {
+                    let request = ctx.request().clone();
+                    let result = spawn_blocking(move || dir.process(&request)).await;
+                    ctx.respond_result(result);
+                }
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::sync::Mutex;
+
+    struct MemoryFile(Mutex<Vec<u8>>);
+
+    impl FileBackend for MemoryFile {
+        fn size(&self) -> io::Result<u64> {
+            Ok(self.0.lock().unwrap().len() as u64)
+        }
+
+        fn read(&self, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+            let data = self.0.lock().unwrap();
+            let start = (offset as usize).min(data.len());
+            let end = start.saturating_add(size).min(data.len());
+            Ok(data[start..end].to_vec())
+        }
+
+        fn write(&self, offset: u64, new_data: &[u8]) -> io::Result<()> {
+            let mut data = self.0.lock().unwrap();
+            let end = offset as usize + new_data.len();
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+            data[offset as usize..end].copy_from_slice(new_data);
+            Ok(())
+        }
+
+        fn truncate(&self, size: u64) -> io::Result<()> {
+            self.0.lock().unwrap().truncate(size as usize);
+            Ok(())
+        }
+    }
+
+    fn call(node: &FileNode, method: &str, param: Option<RpcValue>) -> Result<RpcValue, RpcError> {
+        node.process(&RpcMessage::new_request("file", method, param))
+    }
+
+    fn range(offset: i64, size: i64) -> Option<RpcValue> {
+        Some(vec![RpcValue::from(offset), RpcValue::from(size)].into())
+    }
+
+    #[test]
+    fn read_in_chunks() {
+        let node = FileNode::read_only(MemoryFile(Mutex::new(b"hello world".to_vec()))).with_max_read_size(4);
+        assert_eq!(call(&node, METH_SIZE, None).unwrap().as_int(), 11);
+        assert_eq!(call(&node, METH_READ, range(0, 100)).unwrap().as_blob(), b"hell");
+        assert_eq!(call(&node, METH_READ, range(6, 3)).unwrap().as_blob(), b"wor");
+        assert_eq!(call(&node, METH_READ, None).unwrap_err().code, RpcErrorCode::InvalidParam);
+        assert_eq!(call(&node, METH_WRITE, None).unwrap_err().code, RpcErrorCode::MethodNotFound);
+    }
+
+    #[test]
+    fn checksums() {
+        let node = FileNode::read_only(MemoryFile(Mutex::new(b"hello world".to_vec()))).with_max_read_size(3);
+        assert_eq!(call(&node, METH_CRC, None).unwrap().as_u64(), 0x0d4a1185);
+        assert_eq!(call(&node, METH_CRC, range(0, 5)).unwrap().as_u64(), crc32fast::hash(b"hello") as u64);
+        let sha1 = call(&node, METH_SHA1, None).unwrap();
+        let hex = sha1.as_blob().iter().map(|b| format!("{b:02x}")).collect::<String>();
+        assert_eq!(hex, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+    }
+
+    #[test]
+    fn write_and_truncate() {
+        let node = FileNode::new(MemoryFile(Mutex::new(b"hello".to_vec())));
+        let data: RpcValue = b" world".to_vec().into();
+        call(&node, METH_WRITE, Some(vec![RpcValue::from(5), data].into())).unwrap();
+        assert_eq!(call(&node, METH_READ, range(0, 100)).unwrap().as_blob(), b"hello world");
+        call(&node, METH_TRUNCATE, Some(4.into())).unwrap();
+        assert_eq!(call(&node, METH_SIZE, None).unwrap().as_int(), 4);
+        assert_eq!(call(&node, METH_TRUNCATE, Some("x".into())).unwrap_err().code, RpcErrorCode::InvalidParam);
+    }
+
+    #[test]
+    fn stat_limits() {
+        let node = FileNode::new(MemoryFile(Mutex::new(b"hello".to_vec())))
+            .with_max_read_size(4)
+            .with_max_write_size(8);
+        let stat = call(&node, METH_STAT, None).unwrap();
+        assert_eq!(stat.as_imap().get(&STAT_PAGE_SIZE).map(RpcValue::as_int), Some(4));
+        assert_eq!(stat.as_imap().get(&STAT_MAX_WRITE).map(RpcValue::as_int), Some(8));
+        let data: RpcValue = b"too long data".to_vec().into();
+        assert_eq!(call(&node, METH_WRITE, Some(vec![RpcValue::from(0), data].into())).unwrap_err().code, RpcErrorCode::InvalidParam);
+
+        let node = FileNode::read_only(MemoryFile(Mutex::new(b"hello".to_vec())));
+        let stat = call(&node, METH_STAT, None).unwrap();
+        assert!(!stat.as_imap().contains_key(&STAT_MAX_WRITE));
+    }
+
+    #[test]
+    fn directory_refuses_path_traversal() {
+        let temp_dir = TempDir::new("dirnode");
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.txt"), b"a").unwrap();
+        std::fs::write(root.join("sub/b.txt"), b"bb").unwrap();
+
+        let dir = DirectoryNode::new(root.join("sub"));
+        assert_eq!(dir.children(""), Some(vec!["b.txt".to_string()]));
+        assert_eq!(dir.children("b.txt"), Some(vec![]));
+        assert!(dir.methods("b.txt").unwrap().iter().all(|mm| mm.name != METH_WRITE));
+        assert!(dir.methods("../a.txt").is_none());
+        assert!(dir.children("..").is_none());
+        assert!(dir.methods("missing").is_none());
+        let request = RpcMessage::new_request("../a.txt", METH_READ, range(0, 10));
+        assert_eq!(dir.process(&request).unwrap_err().code, RpcErrorCode::MethodNotFound);
+        let request = RpcMessage::new_request("b.txt", METH_READ, range(0, 10));
+        assert_eq!(dir.process(&request).unwrap().as_blob(), b"bb");
+        let request = RpcMessage::new_request("b.txt", METH_WRITE, Some(vec![RpcValue::from(0), b"x".to_vec().into()].into()));
+        assert_eq!(dir.process(&request).unwrap_err().code, RpcErrorCode::MethodNotFound);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::acl::glob_matches;
+use crate::client::{ClientCommandSender, RequestHandler};
+use crate::clientnode::{send_response, ClientNode, Route, METH_GET, SIG_CHNG};
+use crate::runtime::spawn_blocking;
+use log::warn;
+use shvrpc::metamethod::{AccessLevel, Flag, MetaMethod};
+use shvrpc::rpcmessage::{RpcError, RpcErrorCode};
+use shvrpc::{RpcMessage, RpcMessageMetaTags};
+use shvproto::{rpcvalue, DateTime, RpcValue};
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+
+pub const METH_GET_LOG: &str = "getLog";
+
+const DEFAULT_RECORD_COUNT_LIMIT: usize = 1000;
+const JOURNAL_FILE_NAME: &str = "journal.log";
+
+const JOURNAL_METHODS: [MetaMethod; 1] = [
+    MetaMethod {
+        name: METH_GET_LOG,
+        flags: Flag::None as u32,
+        access: AccessLevel::Read,
+        param: "Map",
+        result: "[[DateTime, String, String, String, RpcValue]]",
+        description: "Recorded signals as [timestamp, path, signal, source, value], params: since, until, pathPattern, recordCountLimit",
+    },
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JournalEntry {
+    pub timestamp: DateTime,
+    pub path: String,
+    pub signal: String,
+    pub source: String,
+    pub value: RpcValue,
+}
+
+impl From<&JournalEntry> for RpcValue {
+    fn from(entry: &JournalEntry) -> Self {
+        let row: rpcvalue::List = vec![
+            entry.timestamp.into(),
+            entry.path.as_str().into(),
+            entry.signal.as_str().into(),
+            entry.source.as_str().into(),
+            entry.value.clone(),
+        ];
+        row.into()
+    }
+}
+
+impl JournalEntry {
+    fn from_rpcvalue(value: &RpcValue) -> Option<Self> {
+        match value.as_list().as_slice() {
+            [timestamp, path, signal, source, value]
+                if timestamp.is_datetime() && path.is_string() && signal.is_string() && source.is_string() => Some(Self {
+                timestamp: timestamp.as_datetime(),
+                path: path.as_str().to_string(),
+                signal: signal.as_str().to_string(),
+                source: source.as_str().to_string(),
+                value: value.clone(),
+            }),
+            // files written before the signal and source were recorded
+            [timestamp, path, value] if timestamp.is_datetime() && path.is_string() => Some(Self {
+                timestamp: timestamp.as_datetime(),
+                path: path.as_str().to_string(),
+                signal: SIG_CHNG.to_string(),
+                source: METH_GET.to_string(),
+                value: value.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Parameters of the `getLog` call
+#[derive(Clone, Debug, Default)]
+pub struct LogQuery {
+    pub since: Option<DateTime>,
+    pub until: Option<DateTime>,
+    pub path_pattern: Option<String>,
+    pub record_count_limit: Option<usize>,
+}
+
+impl LogQuery {
+    fn from_param(param: Option<&RpcValue>) -> Result<Self, String> {
+        let Some(param) = param.filter(|param| !param.is_null()) else {
+            return Ok(Default::default());
+        };
+        if !param.is_map() {
+            return Err(format!("Map expected as a parameter of `{METH_GET_LOG}`"));
+        }
+        let map = param.as_map();
+        let datetime = |key: &str| match map.get(key) {
+            None => Ok(None),
+            Some(value) if value.is_null() => Ok(None),
+            Some(value) if value.is_datetime() => Ok(Some(value.as_datetime())),
+            Some(value) => Err(format!("Invalid `{key}`: {}", value.to_cpon())),
+        };
+        let path_pattern = match map.get("pathPattern") {
+            None => None,
+            Some(value) if value.is_null() => None,
+            Some(value) if value.is_string() => Some(value.as_str().to_string()),
+            Some(value) => return Err(format!("Invalid `pathPattern`: {}", value.to_cpon())),
+        };
+        let record_count_limit = match map.get("recordCountLimit") {
+            None => None,
+            Some(value) if value.is_null() => None,
+            Some(value) if value.is_int() && value.as_int() >= 0 => Some(value.as_int() as usize),
+            Some(value) => return Err(format!("Invalid `recordCountLimit`: {}", value.to_cpon())),
+        };
+        Ok(Self {
+            since: datetime("since")?,
+            until: datetime("until")?,
+            path_pattern,
+            record_count_limit,
+        })
+    }
+
+    fn matches(&self, entry: &JournalEntry) -> bool {
+        self.since.is_none_or(|since| entry.timestamp.epoch_msec() >= since.epoch_msec())
+            && self.until.is_none_or(|until| entry.timestamp.epoch_msec() < until.epoch_msec())
+            && self.path_pattern.as_ref().is_none_or(|pattern| glob_matches(pattern, &entry.path))
+    }
+}
+
+struct JournalFiles {
+    dir: PathBuf,
+    max_file_size: u64,
+    max_files: usize,
+}
+
+impl JournalFiles {
+    fn file_path(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            self.dir.join(JOURNAL_FILE_NAME)
+        } else {
+            self.dir.join(format!("{JOURNAL_FILE_NAME}.{index}"))
+        }
+    }
+
+    fn append(&self, entry: &JournalEntry) -> std::io::Result<()> {
+        let path = self.file_path(0);
+        if std::fs::metadata(&path).is_ok_and(|meta| meta.len() >= self.max_file_size) {
+            self.rotate()?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", RpcValue::from(entry).to_cpon())
+    }
+
+    fn rotate(&self) -> std::io::Result<()> {
+        let _ = std::fs::remove_file(self.file_path(self.max_files));
+        for index in (0..self.max_files).rev() {
+            let path = self.file_path(index);
+            if path.exists() {
+                std::fs::rename(path, self.file_path(index + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    // The files are accessed only from a dedicated thread, so recording
+    // does not block the client loop and reads do not race with rotation
+    fn spawn_worker(self) -> std::io::Result<mpsc::Sender<FileCommand>> {
+        let (command_tx, command_rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("shv-journal".into())
+            .spawn(move || {
+                for command in command_rx {
+                    match command {
+                        FileCommand::Append(entry) => {
+                            if let Err(err) = self.append(&entry) {
+                                warn!("Cannot write journal entry to {}: {err}", self.dir.display());
+                            }
+                        }
+                        FileCommand::Read { query, limit, result_tx } => {
+                            let _ = result_tx.send(self.read(&query, limit));
+                        }
+                    }
+                }
+            })?;
+        Ok(command_tx)
+    }
+
+    fn read(&self, query: &LogQuery, limit: usize) -> Vec<JournalEntry> {
+        (0..=self.max_files)
+            .rev()
+            .map(|index| self.file_path(index))
+            .filter(|path| path.exists())
+            .flat_map(|path| read_entries(&path))
+            .filter(|entry| query.matches(entry))
+            .take(limit)
+            .collect()
+    }
+}
+
+fn read_entries(path: &Path) -> Vec<JournalEntry> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            warn!("Cannot open journal file {}: {err}", path.display());
+            return Vec::new();
+        }
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| RpcValue::from_cpon(&line).ok())
+        .filter_map(|value| JournalEntry::from_rpcvalue(&value))
+        .collect()
+}
+
+enum FileCommand {
+    Append(JournalEntry),
+    Read {
+        query: LogQuery,
+        limit: usize,
+        result_tx: mpsc::Sender<Vec<JournalEntry>>,
+    },
+}
+
+struct JournalInner {
+    entries: Mutex<VecDeque<JournalEntry>>,
+    capacity: usize,
+    files: Option<mpsc::Sender<FileCommand>>,
+}
+
+/// A journal of value changes
+///
+/// The client records every `chng` signal it sends into the journal
+/// mounted by `Client::mount_journal()`. The latest entries are kept in
+/// memory, optionally the entries are written also to rotating files
+/// by a background thread. The journal is queried by `getLog`, which
+/// reads the files when the requested time range is not available in
+/// memory. A record consists of the timestamp, path, signal name, source
+/// method and value as in the SHV `getLog` format.
+#[derive(Clone)]
+pub struct Journal {
+    inner: Arc<JournalInner>,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(JournalInner {
+                entries: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity,
+                files: None,
+            }),
+        }
+    }
+
+    /// Creates a journal which also writes the entries to `journal.log`
+    /// in `dir`. The file is rotated after reaching `max_file_size` bytes
+    /// and at most `max_files` rotated files are kept.
+    pub fn with_files(capacity: usize, dir: impl Into<PathBuf>, max_file_size: u64, max_files: usize) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let files = JournalFiles { dir, max_file_size, max_files }.spawn_worker()?;
+        Ok(Self {
+            inner: Arc::new(JournalInner {
+                entries: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity,
+                files: Some(files),
+            }),
+        })
+    }
+
+    /// Records a `chng` of a property value
+    pub fn record(&self, path: impl Into<String>, value: RpcValue) {
+        self.record_signal(path, SIG_CHNG, METH_GET, value);
+    }
+
+    /// Records a signal with the method it is emitted by as the source
+    pub fn record_signal(&self, path: impl Into<String>, signal: impl Into<String>, source: impl Into<String>, value: RpcValue) {
+        let entry = JournalEntry {
+            timestamp: DateTime::now(),
+            path: path.into(),
+            signal: signal.into(),
+            source: source.into(),
+            value,
+        };
+        let mut entries = self.inner.entries.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(files) = &self.inner.files {
+            if files.send(FileCommand::Append(entry.clone())).is_err() {
+                warn!("Journal file writer is not running, the entry is not written");
+            }
+        }
+        if self.inner.capacity == 0 {
+            return;
+        }
+        if entries.len() == self.inner.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    pub fn query(&self, query: &LogQuery) -> Vec<JournalEntry> {
+        let limit = query.record_count_limit.unwrap_or(DEFAULT_RECORD_COUNT_LIMIT);
+        {
+            let entries = self.inner.entries.lock().unwrap_or_else(|err| err.into_inner());
+            let in_memory = self.inner.files.is_none() || entries.front().is_some_and(|oldest| {
+                query.since.is_some_and(|since| since.epoch_msec() >= oldest.timestamp.epoch_msec())
+            });
+            if in_memory {
+                return entries
+                    .iter()
+                    .filter(|entry| query.matches(entry))
+                    .take(limit)
+                    .cloned()
+                    .collect();
+            }
+        }
+        // The entries are not locked while the files are read
+        let (result_tx, result_rx) = mpsc::channel();
+        let read = FileCommand::Read { query: query.clone(), limit, result_tx };
+        if self.inner.files.as_ref().is_some_and(|files| files.send(read).is_ok()) {
+            if let Ok(entries) = result_rx.recv() {
+                return entries;
+            }
+        }
+        warn!("Journal file reader is not running");
+        Vec::new()
+    }
+
+    async fn process_request(&self, request: RpcMessage, client_cmd_tx: ClientCommandSender) {
+        let result = match request.method() {
+            Some(METH_GET_LOG) => match LogQuery::from_param(request.param()) {
+                Ok(query) => {
+                    let journal = self.clone();
+                    let entries = spawn_blocking(move || journal.query(&query)).await;
+                    Ok(entries.iter().map(RpcValue::from).collect::<rpcvalue::List>().into())
+                }
+                Err(err) => Err(RpcError::new(RpcErrorCode::InvalidParam, err)),
+            },
+            method => Err(RpcError::new(
+                    RpcErrorCode::MethodNotFound,
+                    format!("Invalid method: {method:?}"))),
+        };
+        send_response(request, client_cmd_tx, result);
+    }
+
+    pub(crate) fn client_node<T: Send + Sync + 'static>(&self) -> ClientNode<'static, T> {
+        let journal = self.clone();
+        let handler = RequestHandler::stateless(move |request, client_cmd_tx| {
+            let journal = journal.clone();
+            async move { journal.process_request(request, client_cmd_tx).await }
+        });
+        ClientNode::fixed(&JOURNAL_METHODS, [Route::new([METH_GET_LOG], handler)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn paths(entries: &[JournalEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.path.as_str()).collect()
+    }
+
+    #[test]
+    fn ring_buffer_is_bounded() {
+        let journal = Journal::new(3);
+        for i in 0..5 {
+            journal.record(format!("dev/p{i}"), i.into());
+        }
+        assert_eq!(paths(&journal.query(&Default::default())), ["dev/p2", "dev/p3", "dev/p4"]);
+    }
+
+    #[test]
+    fn query_filters_entries() {
+        let journal = Journal::new(10);
+        journal.record("dev/temp", 1.into());
+        journal.record("dev/status/run", true.into());
+        journal.record("other/temp", 2.into());
+        journal.record("dev/temp", 3.into());
+
+        let query = LogQuery { path_pattern: Some("dev/**".into()), ..Default::default() };
+        assert_eq!(paths(&journal.query(&query)), ["dev/temp", "dev/status/run", "dev/temp"]);
+
+        let query = LogQuery { path_pattern: Some("*/temp".into()), record_count_limit: Some(2), ..Default::default() };
+        assert_eq!(paths(&journal.query(&query)), ["dev/temp", "other/temp"]);
+
+        let future = DateTime::from_epoch_msec(DateTime::now().epoch_msec() + 60_000);
+        let query = LogQuery { since: Some(future), ..Default::default() };
+        assert!(journal.query(&query).is_empty());
+    }
+
+    #[test]
+    fn parse_query() {
+        let query = LogQuery::from_param(Some(&RpcValue::from_cpon(r#"{"pathPattern": "a/**", "recordCountLimit": 5}"#).unwrap())).unwrap();
+        assert_eq!(query.path_pattern.as_deref(), Some("a/**"));
+        assert_eq!(query.record_count_limit, Some(5));
+        assert!(query.since.is_none());
+        assert!(LogQuery::from_param(None).is_ok());
+        assert!(LogQuery::from_param(Some(&"a".into())).is_err());
+        assert!(LogQuery::from_param(Some(&RpcValue::from_cpon(r#"{"since": "yesterday"}"#).unwrap())).is_err());
+    }
+
+    #[test]
+    fn entry_rows() {
+        let journal = Journal::new(1);
+        journal.record_signal("dev/status", "alarmmod", "alarms", true.into());
+        let entry = journal.query(&Default::default()).remove(0);
+        let row = RpcValue::from(&entry);
+        assert_eq!(row.as_list()[1..4].iter().map(RpcValue::as_str).collect::<Vec<_>>(), ["dev/status", "alarmmod", "alarms"]);
+        assert_eq!(JournalEntry::from_rpcvalue(&row), Some(entry));
+
+        let row = RpcValue::from_cpon(r#"[d"2024-01-01T00:00:00Z", "dev/temp", 1]"#).unwrap();
+        let entry = JournalEntry::from_rpcvalue(&row).unwrap();
+        assert_eq!((entry.signal.as_str(), entry.source.as_str()), (SIG_CHNG, METH_GET));
+    }
+
+    #[test]
+    fn rotate_files() {
+        let dir = TempDir::new("journal");
+        let journal = Journal::with_files(2, dir.path(), 1, 2).unwrap();
+        for i in 0..5 {
+            journal.record(format!("p{i}"), i.into());
+        }
+        // older entries than in the ring buffer are read from the files,
+        // the read is queued behind the writes, so they are done after it
+        let query = LogQuery { since: Some(DateTime::from_epoch_msec(0)), ..Default::default() };
+        assert_eq!(paths(&journal.query(&query)), ["p2", "p3", "p4"]);
+        assert!(dir.path().join("journal.log.2").exists());
+        assert!(!dir.path().join("journal.log.3").exists());
+    }
+}
@@ -1,17 +1,43 @@
+pub mod acl;
+pub mod alarms;
 pub mod appnodes;
+#[cfg(feature = "loopback_broker")]
+pub mod broker;
 pub mod client;
 pub mod runtime;
 pub mod clientnode;
+pub mod filenode;
+pub mod journal;
+pub mod middleware;
+pub mod offlinebuffer;
+pub mod propertynode;
+pub mod recording;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod connection;
 mod macros;
+mod signalemitter;
+mod typename;
 
+pub use acl::AccessRules;
+pub use alarms::{Alarms, AlarmSeverity};
 pub use client::{
     AppState,
+    ChildrenGetter,
     Client,
     ClientCommandSender,
     ClientEvent,
     ClientEventsReceiver,
     MethodsGetter,
+    RequestContext,
     RequestHandler,
 };
-pub use clientnode::Route;
+pub use clientnode::{NodeTree, RequestAccess, Route, ShvNode};
+pub use filenode::{DirectoryNode, FileNode};
+pub use journal::Journal;
+pub use connection::{FrameDirection, FrameObserver, FrameTrace};
+pub use middleware::Middleware;
+pub use offlinebuffer::{OfflineBufferOptions, OfflineBufferStats, OverflowPolicy};
+pub use propertynode::PropertyNode;
+#[cfg(feature = "macros")]
+pub use shvclient_macros::{shv_node, ShvNode};
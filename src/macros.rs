@@ -34,12 +34,39 @@
 ///             }
 ///             "setTable" [IsGetter, Browse] (table: MyTable) => {
 ///                 handle_table(table, client_cmd_tx);
-///                 // The response is sent in `handle_table` above, so we return `None`
-///                 // to indicate that the generated code shouldn't send the response.
-///                 None
+///                 // The response is sent in `handle_table` above, so we return
+///                 // `DeferredResponse` (or `None`) to indicate that the generated
+///                 // code shouldn't send the response.
+///                 DeferredResponse
 ///             }
+///             // Bodies can return any type implementing `IntoMethodResult`, e.g.
+///             // values convertible to `RpcValue` or `Result<T, E>` where
+///             // `T: Into<RpcValue>` and `E: Into<RpcError>`.
 ///             "version" [IsGetter, Browse] => {
-///                 Some(Ok(RpcValue::from(42)))
+///                 42
+///             }
+///             // Several parameters are taken from the fields of a Map param by
+///             // their names, or from the items of a List param by their order.
+///             // `Option<T>` parameters may be missing or null, the same holds for
+///             // parameters with a default value. A single `Option<T>` or defaulted
+///             // parameter applies to the whole request param.
+///             "setRange" [IsSetter, Write] (min: i32, max: i32, step: Option<i32>, wrap: bool = false) => {
+///                 println!("range: {min}..{max}, step: {step:?}, wrap: {wrap}");
+///                 Ok(())
+///             }
+///             // Optional metadata shown in `dir` follow the access level in this order:
+///             // `description`, `param`, `result` and `signals`. Type names in `param`
+///             // and `result` default to the SHV names of the types of `(param: Type)`
+///             // and `-> Type`, e.g. `Option<Vec<i32>>` is shown as `[Int]|Null`.
+///             "status" [IsGetter, Read, description: "Device status", signals: ["statuschng"]] -> String => {
+///                 "ok"
+///             }
+///             "setMode" [IsSetter, Write, param: "Int", result: "Null"] (mode: i32) => {
+///                 if mode < 0 {
+///                     return Err(RpcError::new(RpcErrorCode::InvalidParam, "Negative mode"));
+///                 }
+///                 println!("mode: {}", mode);
+///                 Ok(())
 ///             }
 ///         }
 ///     }
@@ -48,7 +75,12 @@
 #[macro_export]
 macro_rules! fixed_node {
     ($fn_name:ident ( $request:ident, $client_cmd_tx:ident $(, $app_state:ident: $T:ty)?) {
-        $($method:tt [$($flags:ident)|+, $access:ident] $(($param:ident : $type:ty))? => $body:block)+
+        $($method:tt [$($flags:ident)|+, $access:ident
+            $(, description: $description:literal)?
+            $(, param: $param_name:literal)?
+            $(, result: $result_name:literal)?
+            $(, signals: [$($signal:literal),* $(,)?])?
+        ] $(($($params:tt)+))? $(-> $result:ty)? => $body:block)+
     }) => {
 
         {
@@ -57,9 +89,9 @@ macro_rules! fixed_node {
                     name: $method,
                     flags: $($crate::clientnode::Flag::$flags as u32)|+,
                     access: $crate::clientnode::AccessLevel::$access,
-                    param: "",
-                    result: "",
-                    description: "",
+                    param: $crate::meta_param_type!($($param_name)? ; $($($params)+)?),
+                    result: $crate::meta_type_name!($($result_name)? ; $($result)?),
+                    description: $crate::meta_type_name!($($description)? ;),
                 },)+
             ];
 
@@ -74,7 +106,7 @@ macro_rules! fixed_node {
                         match $request.method() {
 
                             $(Some($method) => {
-                                $crate::method_handler!($(($param : $type))? $method @ $request @ $body)
+                                $crate::method_handler!($(($($params)+))? $method @ $request @ $body)
                             })+
 
                             _ => Some(Err($crate::clientnode::RpcError::new(
@@ -105,6 +137,7 @@ macro_rules! fixed_node {
                     $crate::request_handler!($fn_name $(,$app_state)?),
                 )]
             )
+            .with_signals(&[$($($(($method, $signal),)*)?)+])
         }
     }
 }
@@ -121,12 +154,46 @@ macro_rules! request_handler {
 
 #[macro_export]
 macro_rules! method_handler {
+    // A single parameter is converted from the whole request param
+    (($param:ident : Option<$inner:ty>) $method:tt @ $request:ident @ $body:block) => {
+        {
+            let request_param = $request.param().filter(|value| !value.is_null());
+
+            match request_param.map(<$inner>::try_from).transpose() {
+                Ok($param) => $crate::method_handler!($method @ $request @ $body),
+                Err(err) => Some(Err($crate::clientnode::RpcError::new(
+                                $crate::clientnode::RpcErrorCode::InvalidParam,
+                                format!("Wrong parameter for `{}`: {}",
+                                    $method,
+                                    err
+                                ))))
+            }
+        }
+    };
+    (($param:ident : $type:ty = $default:expr) $method:tt @ $request:ident @ $body:block) => {
+        {
+            let request_param = $request.param().filter(|value| !value.is_null());
+
+            match request_param.map(<$type>::try_from).transpose() {
+                Ok(value) => {
+                    let $param: $type = value.unwrap_or_else(|| $default);
+                    $crate::method_handler!($method @ $request @ $body)
+                }
+                Err(err) => Some(Err($crate::clientnode::RpcError::new(
+                                $crate::clientnode::RpcErrorCode::InvalidParam,
+                                format!("Wrong parameter for `{}`: {}",
+                                    $method,
+                                    err
+                                ))))
+            }
+        }
+    };
     (($param:ident : $type:ty) $method:tt @ $request:ident @ $body:block) => {
         {
             let request_param = $request.param().unwrap_or_default();
 
              match <$type>::try_from(request_param) {
-                 Ok($param) => $body,
+                 Ok($param) => $crate::method_handler!($method @ $request @ $body),
                  Err(err) => Some(Err($crate::clientnode::RpcError::new(
                                  $crate::clientnode::RpcErrorCode::InvalidParam,
                                  format!("Wrong parameter for `{}`: {}",
@@ -136,9 +203,87 @@ macro_rules! method_handler {
             }
         }
     };
+    // Several parameters are fields of a Map param, or items of a List param
+    (($($params:tt)+) $method:tt @ $request:ident @ $body:block) => {
+        {
+            let request_param = $request.param().filter(|value| !value.is_null());
+            if request_param.is_some_and(|value| !value.is_map() && !value.is_list()) {
+                return Some(Err($crate::clientnode::RpcError::new(
+                            $crate::clientnode::RpcErrorCode::InvalidParam,
+                            format!("Wrong parameter for `{}`: expected Map or List", $method))));
+            }
+            $crate::method_params!(request_param, $method, 0usize; $($params)+);
+            $crate::method_handler!($method @ $request @ $body)
+        }
+    };
     ($method:tt @ $request:ident @ $body:block) => {
-        $body
+        $crate::clientnode::IntoMethodResult::into_method_result(async move $body.await)
+    };
+}
+
+// Binds the fields of a request param to variables. Returns from the
+// enclosing function with an `InvalidParam` error on a failure.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! method_params {
+    ($request_param:ident, $method:tt, $index:expr;) => { };
+    ($request_param:ident, $method:tt, $index:expr; $param:ident : Option<$inner:ty> $(, $($rest:tt)*)?) => {
+        let $param: Option<$inner> = match $crate::clientnode::param_field($request_param, stringify!($param), $index) {
+            None => None,
+            Some(value) => Some($crate::method_params!(@convert value, $param, $method, $inner)),
+        };
+        $crate::method_params!($request_param, $method, $index + 1usize; $($($rest)*)?);
+    };
+    ($request_param:ident, $method:tt, $index:expr; $param:ident : $type:ty = $default:expr $(, $($rest:tt)*)?) => {
+        let $param: $type = match $crate::clientnode::param_field($request_param, stringify!($param), $index) {
+            None => $default,
+            Some(value) => $crate::method_params!(@convert value, $param, $method, $type),
+        };
+        $crate::method_params!($request_param, $method, $index + 1usize; $($($rest)*)?);
+    };
+    ($request_param:ident, $method:tt, $index:expr; $param:ident : $type:ty $(, $($rest:tt)*)?) => {
+        let $param: $type = match $crate::clientnode::param_field($request_param, stringify!($param), $index) {
+            None => return Some(Err($crate::clientnode::RpcError::new(
+                        $crate::clientnode::RpcErrorCode::InvalidParam,
+                        format!("Missing field `{}` in parameter for `{}`", stringify!($param), $method)))),
+            Some(value) => $crate::method_params!(@convert value, $param, $method, $type),
+        };
+        $crate::method_params!($request_param, $method, $index + 1usize; $($($rest)*)?);
+    };
+    (@convert $value:ident, $param:ident, $method:tt, $type:ty) => {
+        match <$type>::try_from($value) {
+            Ok(value) => value,
+            Err(err) => return Some(Err($crate::clientnode::RpcError::new(
+                        $crate::clientnode::RpcErrorCode::InvalidParam,
+                        format!("Wrong value of field `{}` in parameter for `{}`: {}", stringify!($param), $method, err)))),
+        }
+    };
+}
+
+// Resolves a name of a type in method metadata. An explicit name takes
+// precedence over the name of the type binding.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! meta_type_name {
+    ($name:literal ; $($type:ty)?) => { $name };
+    (; $type:ty) => { $crate::clientnode::shv_type_name(stringify!($type)).as_str() };
+    (;) => { "" };
+}
+
+// Resolves a name of the parameter type in method metadata
+#[doc(hidden)]
+#[macro_export]
+macro_rules! meta_param_type {
+    ($name:literal ; $($params:tt)*) => { $name };
+    (; $param:ident : $type:ty $(= $default:expr)?) => { $crate::clientnode::shv_type_name(stringify!($type)).as_str() };
+    (; $param:ident : $type:ty $(= $default:expr)?, $($rest:tt)+) => {
+        $crate::meta_param_type!(@fields [stringify!($param), ": ", stringify!($type),] $($rest)+)
+    };
+    (;) => { "" };
+    (@fields [$($acc:tt)*] $param:ident : $type:ty $(= $default:expr)? $(, $($rest:tt)*)?) => {
+        $crate::meta_param_type!(@fields [$($acc)* ", ", stringify!($param), ": ", stringify!($type),] $($($rest)*)?)
     };
+    (@fields [$($acc:tt)*]) => { $crate::clientnode::shv_type_name(concat!("{", $($acc)* "}")).as_str() };
 }
 
 #[macro_export]
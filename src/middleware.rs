@@ -0,0 +1,23 @@
+use shvrpc::rpcmessage::RpcError;
+use shvrpc::RpcMessage;
+use shvproto::RpcValue;
+
+/// Cross-cutting processing of the client traffic
+///
+/// Middlewares are registered on a `Client` by `Client::add_middleware()`
+/// and are invoked in the order of registration.
+///
+/// `process_request` is called for every incoming request before it is
+/// dispatched to the mounted nodes. The request still carries the full SHV
+/// path. Returning `Some` short-circuits the dispatch, the result is sent
+/// as the response and the remaining middlewares are skipped.
+///
+/// `process_response` is called for every response the client sends,
+/// including the responses produced by the middlewares.
+pub trait Middleware: Send + Sync {
+    fn process_request(&self, _request: &RpcMessage) -> Option<Result<RpcValue, RpcError>> {
+        None
+    }
+
+    fn process_response(&self, _response: &RpcMessage) { }
+}
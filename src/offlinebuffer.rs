@@ -0,0 +1,157 @@
+use shvrpc::{RpcMessage, RpcMessageMetaTags};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// What to drop when the offline buffer is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest buffered message to make room for the new one
+    #[default]
+    DropOldest,
+    /// Drop the new message
+    DropNewest,
+}
+
+pub struct OfflineBufferOptions {
+    /// Maximal number of buffered messages
+    pub capacity: usize,
+    /// Buffer also the outgoing requests, not only the signals
+    pub buffer_requests: bool,
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl Default for OfflineBufferOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            buffer_requests: false,
+            overflow_policy: Default::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    dropped_signals: AtomicU64,
+    dropped_requests: AtomicU64,
+    dropped_responses: AtomicU64,
+}
+
+/// Counters of the messages dropped while the client was disconnected
+#[derive(Clone, Default)]
+pub struct OfflineBufferStats(Arc<Counters>);
+
+impl OfflineBufferStats {
+    pub fn dropped_signals(&self) -> u64 {
+        self.0.dropped_signals.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_requests(&self) -> u64 {
+        self.0.dropped_requests.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_responses(&self) -> u64 {
+        self.0.dropped_responses.load(Ordering::Relaxed)
+    }
+
+    fn count_dropped(&self, message: &RpcMessage) {
+        let counter = if message.is_signal() {
+            &self.0.dropped_signals
+        } else if message.is_request() {
+            &self.0.dropped_requests
+        } else {
+            &self.0.dropped_responses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Keeps the messages sent while the client is disconnected until
+// the client connects again. Responses are never buffered, because
+// the callers are gone after a reconnection.
+pub(crate) struct OfflineBuffer {
+    options: OfflineBufferOptions,
+    messages: VecDeque<RpcMessage>,
+    stats: OfflineBufferStats,
+}
+
+impl OfflineBuffer {
+    pub(crate) fn new(options: OfflineBufferOptions) -> Self {
+        Self {
+            options,
+            messages: VecDeque::new(),
+            stats: Default::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> OfflineBufferStats {
+        self.stats.clone()
+    }
+
+    pub(crate) fn push(&mut self, message: RpcMessage) {
+        let bufferable = message.is_signal() || (self.options.buffer_requests && message.is_request());
+        if !bufferable || self.options.capacity == 0 {
+            self.stats.count_dropped(&message);
+            return;
+        }
+        if self.messages.len() >= self.options.capacity {
+            match self.options.overflow_policy {
+                OverflowPolicy::DropOldest => {
+                    if let Some(dropped) = self.messages.pop_front() {
+                        self.stats.count_dropped(&dropped);
+                    }
+                }
+                OverflowPolicy::DropNewest => {
+                    self.stats.count_dropped(&message);
+                    return;
+                }
+            }
+        }
+        self.messages.push_back(message);
+    }
+
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = RpcMessage> + '_ {
+        self.messages.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(value: i32) -> RpcMessage {
+        RpcMessage::new_signal("test/value", "chng", Some(value.into()))
+    }
+
+    fn drained_values(buffer: &mut OfflineBuffer) -> Vec<i32> {
+        buffer.drain().map(|msg| msg.param().unwrap().as_i32()).collect()
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let mut buffer = OfflineBuffer::new(OfflineBufferOptions { capacity: 2, ..Default::default() });
+        (1..=3).for_each(|value| buffer.push(signal(value)));
+        buffer.push(RpcMessage::new_request("test", "get", None));
+        assert_eq!(drained_values(&mut buffer), [2, 3]);
+        assert_eq!(buffer.stats().dropped_signals(), 1);
+        assert_eq!(buffer.stats().dropped_requests(), 1);
+        assert!(buffer.drain().next().is_none());
+    }
+
+    #[test]
+    fn drop_newest() {
+        let mut buffer = OfflineBuffer::new(OfflineBufferOptions {
+            capacity: 2,
+            buffer_requests: true,
+            overflow_policy: OverflowPolicy::DropNewest,
+        });
+        buffer.push(RpcMessage::new_request("test", "get", None));
+        (1..=3).for_each(|value| buffer.push(signal(value)));
+        let drained = buffer.drain().collect::<Vec<_>>();
+        assert!(drained[0].is_request());
+        assert_eq!(drained[1].param().unwrap().as_i32(), 1);
+        assert_eq!(buffer.stats().dropped_signals(), 2);
+        assert_eq!(buffer.stats().dropped_requests(), 0);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::client::{ClientCommandSender, RequestHandler};
+use crate::clientnode::{send_response, ClientNode, Route, METH_GET, METH_SET, SIG_CHNG};
+use shvrpc::metamethod::{AccessLevel, Flag, MetaMethod};
+use shvrpc::rpcmessage::{RpcError, RpcErrorCode};
+use shvrpc::{RpcMessage, RpcMessageMetaTags};
+use shvproto::RpcValue;
+use crate::signalemitter::SignalEmitter;
+use std::sync::{Arc, RwLock};
+
+const READ_WRITE_PROPERTY_METHODS: [MetaMethod; 2] = [
+    MetaMethod {
+        name: METH_GET,
+        flags: Flag::IsGetter as u32,
+        access: AccessLevel::Read,
+        param: "",
+        result: "",
+        description: "",
+    },
+    MetaMethod {
+        name: METH_SET,
+        flags: Flag::IsSetter as u32,
+        access: AccessLevel::Write,
+        param: "",
+        result: "",
+        description: "",
+    },
+];
+
+const READ_ONLY_PROPERTY_METHODS: [MetaMethod; 1] = [
+    MetaMethod {
+        name: METH_GET,
+        flags: Flag::IsGetter as u32,
+        access: AccessLevel::Read,
+        param: "",
+        result: "",
+        description: "",
+    },
+];
+
+const PROPERTY_SIGNALS: [(&str, &str); 1] = [(METH_GET, SIG_CHNG)];
+
+/// Types that can be stored in a `PropertyNode`
+pub trait PropertyValue:
+    Clone
+    + PartialEq
+    + Into<RpcValue>
+    + for<'a> TryFrom<&'a RpcValue, Error = String>
+    + Send
+    + Sync
+    + 'static
+{ }
+
+impl<V> PropertyValue for V
+where
+    V: Clone
+        + PartialEq
+        + Into<RpcValue>
+        + for<'a> TryFrom<&'a RpcValue, Error = String>
+        + Send
+        + Sync
+        + 'static
+{ }
+
+struct PropertyInner<V> {
+    value: RwLock<V>,
+    read_only: bool,
+    emitter: SignalEmitter,
+}
+
+/// A node holding a typed value
+///
+/// The node answers `get` and `set` (unless it is read-only) and emits
+/// `chng` whenever the value changes, either by `set` from a client or
+/// by `PropertyNode::set()` from the application code. The handle is
+/// cheap to clone, mount it by `Client::mount_property()`.
+pub struct PropertyNode<V> {
+    inner: Arc<PropertyInner<V>>,
+    methods: &'static [MetaMethod],
+}
+
+impl<V> Clone for PropertyNode<V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            methods: self.methods,
+        }
+    }
+}
+
+impl<V: PropertyValue> PropertyNode<V> {
+    pub fn new(value: V) -> Self {
+        Self::create(value, false)
+    }
+
+    pub fn read_only(value: V) -> Self {
+        Self::create(value, true)
+    }
+
+    fn create(value: V, read_only: bool) -> Self {
+        Self {
+            inner: Arc::new(PropertyInner {
+                value: RwLock::new(value),
+                read_only,
+                emitter: Default::default(),
+            }),
+            methods: if read_only { &READ_ONLY_PROPERTY_METHODS } else { &READ_WRITE_PROPERTY_METHODS },
+        }
+    }
+
+    /// Replaces the default methods table, e.g. to set custom access
+    /// levels or descriptions. The table may define only `get` and `set`
+    /// (if the property is not read-only). The `chng` signal is attached
+    /// to `get`.
+    pub fn with_methods(mut self, methods: &'static [MetaMethod]) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.inner.read_only
+    }
+
+    pub fn get(&self) -> V {
+        self.inner.value.read().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    /// Sets a new value and emits `chng` if the value has changed.
+    /// Returns `true` if the value has changed.
+    pub fn set(&self, value: V) -> bool {
+        let mut current = self.inner.value.write().unwrap_or_else(|err| err.into_inner());
+        if *current == value {
+            return false;
+        }
+        *current = value.clone();
+        // Emit while holding the lock to keep the signals in order of the changes
+        self.emit_chng(value);
+        true
+    }
+
+    /// Modifies the value in place and emits `chng` if the value has changed.
+    pub fn update(&self, f: impl FnOnce(&mut V)) -> bool {
+        let mut current = self.inner.value.write().unwrap_or_else(|err| err.into_inner());
+        let orig = current.clone();
+        f(&mut current);
+        if *current == orig {
+            return false;
+        }
+        self.emit_chng(current.clone());
+        true
+    }
+
+    fn emit_chng(&self, value: V) {
+        self.inner.emitter.emit(SIG_CHNG, value.into());
+    }
+
+    pub(crate) fn attach(&self, path: &str, client_cmd_tx: ClientCommandSender) {
+        self.inner.emitter.attach(path, client_cmd_tx);
+    }
+
+    fn process_request(&self, request: RpcMessage, client_cmd_tx: ClientCommandSender) {
+        let result = match request.method() {
+            Some(METH_GET) => Ok(self.get().into()),
+            Some(METH_SET) if !self.inner.read_only => {
+                match request.param().map(V::try_from) {
+                    Some(Ok(value)) => {
+                        self.set(value);
+                        Ok(RpcValue::null())
+                    }
+                    Some(Err(err)) => Err(RpcError::new(
+                            RpcErrorCode::InvalidParam,
+                            format!("Wrong parameter for `{METH_SET}`: {err}"))),
+                    None => Err(RpcError::new(
+                            RpcErrorCode::InvalidParam,
+                            format!("Missing parameter for `{METH_SET}`"))),
+                }
+            }
+            method => Err(RpcError::new(
+                    RpcErrorCode::MethodNotFound,
+                    format!("Invalid method: {method:?}"))),
+        };
+        send_response(request, client_cmd_tx, result);
+    }
+
+    pub(crate) fn client_node<T: Send + Sync + 'static>(&self) -> ClientNode<'static, T> {
+        let property = self.clone();
+        let handler = RequestHandler::stateless(move |request, client_cmd_tx| {
+            property.process_request(request, client_cmd_tx);
+            async { }
+        });
+        let routed_methods = self.methods
+            .iter()
+            .filter(|mm| mm.name == METH_GET || mm.name == METH_SET)
+            .map(|mm| mm.name);
+        ClientNode::fixed(self.methods, [Route::new(routed_methods, handler)])
+            .with_signals(&PROPERTY_SIGNALS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ClientCommand, Receiver};
+    use crate::signalemitter::test_utils::{command_channel, expect_signal};
+
+    fn attached_property(value: i32) -> (PropertyNode<i32>, Receiver<ClientCommand>) {
+        let (client_cmd_tx, receiver) = command_channel();
+        let property = PropertyNode::new(value);
+        property.attach("test/property", client_cmd_tx);
+        (property, receiver)
+    }
+
+    fn expect_chng(receiver: &mut Receiver<ClientCommand>, value: i32) {
+        assert_eq!(expect_signal(receiver, "test/property", SIG_CHNG), RpcValue::from(value));
+    }
+
+    #[test]
+    fn set_emits_chng_on_change() {
+        let (property, mut receiver) = attached_property(1);
+        assert!(property.set(2));
+        expect_chng(&mut receiver, 2);
+        assert!(!property.set(2));
+        assert!(receiver.try_next().is_err());
+        assert_eq!(property.get(), 2);
+    }
+
+    #[test]
+    fn update_emits_chng_on_change() {
+        let (property, mut receiver) = attached_property(1);
+        assert!(property.update(|v| *v += 10));
+        expect_chng(&mut receiver, 11);
+        assert!(!property.update(|_| { }));
+        assert!(receiver.try_next().is_err());
+    }
+
+    #[test]
+    fn detached_property_stores_value() {
+        let property = PropertyNode::new(String::from("foo"));
+        assert!(property.set("bar".into()));
+        assert_eq!(property.get(), "bar");
+    }
+}
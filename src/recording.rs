@@ -0,0 +1,413 @@
+use crate::client::Sender;
+use crate::connection::{ConnectionCommand, ConnectionEvent, FrameDirection, FrameObserver, FrameObserverRef, FrameTrace};
+use futures::{select, FutureExt, StreamExt};
+use log::*;
+use shvrpc::rpcframe::RpcFrame;
+use shvrpc::RpcMessage;
+use shvproto::RpcValue;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DIR_RECEIVED: &str = "<";
+const DIR_SENT: &str = ">";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// One CPON record per line
+    Cpon,
+    /// Length prefixed ChainPack records
+    ChainPack,
+}
+
+/// A single frame of a recorded session
+///
+/// Every record is serialized as a list `[timestamp, direction, message]`,
+/// where timestamp is in milliseconds since the Unix epoch and direction
+/// is `"<"` for received and `">"` for sent frames.
+pub struct TrafficRecord {
+    pub timestamp: i64,
+    pub direction: FrameDirection,
+    pub message: RpcMessage,
+}
+
+impl TrafficRecord {
+    fn to_rpcvalue(&self) -> RpcValue {
+        let direction = match self.direction {
+            FrameDirection::Received => DIR_RECEIVED,
+            FrameDirection::Sent => DIR_SENT,
+        };
+        let record: shvproto::rpcvalue::List = vec![
+            self.timestamp.into(),
+            direction.into(),
+            self.message.as_rpcvalue().clone(),
+        ];
+        record.into()
+    }
+
+    fn from_rpcvalue(value: RpcValue) -> shvrpc::Result<Self> {
+        let [timestamp, direction, message] = value.as_list().as_slice() else {
+            return Err(format!("Invalid traffic record: {}", value.to_cpon()).into());
+        };
+        let direction = match direction.as_str() {
+            DIR_RECEIVED => FrameDirection::Received,
+            DIR_SENT => FrameDirection::Sent,
+            dir => return Err(format!("Invalid traffic record direction: {dir}").into()),
+        };
+        let message = RpcMessage::from_rpcvalue(message.clone())
+            .map_err(|err| format!("Invalid traffic record message: {err}"))?;
+        Ok(Self {
+            timestamp: timestamp.as_int(),
+            direction,
+            message,
+        })
+    }
+}
+
+fn write_record(writer: &mut impl Write, record: &TrafficRecord, format: RecordFormat) -> std::io::Result<()> {
+    let value = record.to_rpcvalue();
+    match format {
+        RecordFormat::Cpon => writeln!(writer, "{}", value.to_cpon()),
+        RecordFormat::ChainPack => {
+            let data = value.to_chainpack();
+            writer.write_all(&(data.len() as u32).to_be_bytes())?;
+            writer.write_all(&data)
+        }
+    }
+}
+
+/// Frame observer writing all the traffic of a connection
+///
+/// Install it on a client by `Client::with_frame_observer()`.
+/// The records are written by a background thread, so the connection
+/// task is not blocked. The writer is flushed when the recorder is dropped.
+pub struct TrafficRecorder {
+    record_tx: Option<mpsc::Sender<TrafficRecord>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl TrafficRecorder {
+    pub fn new(mut writer: impl Write + Send + 'static, format: RecordFormat) -> Self {
+        let (record_tx, record_rx) = mpsc::channel();
+        let worker = std::thread::Builder::new()
+            .name("shv-recorder".into())
+            .spawn(move || {
+                for record in record_rx {
+                    if let Err(err) = write_record(&mut writer, &record, format) {
+                        error!("Cannot write traffic record: {err}");
+                    }
+                }
+                if let Err(err) = writer.flush() {
+                    error!("Cannot flush traffic records: {err}");
+                }
+            })
+            .expect("Cannot spawn the traffic recorder thread");
+        Self {
+            record_tx: Some(record_tx),
+            worker: Some(worker),
+        }
+    }
+
+    pub fn create(path: impl AsRef<Path>, format: RecordFormat) -> std::io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), format))
+    }
+}
+
+impl Drop for TrafficRecorder {
+    fn drop(&mut self) {
+        // Closing the channel lets the worker write out the queued records
+        drop(self.record_tx.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl FrameObserver for TrafficRecorder {
+    fn observe(&self, trace: &FrameTrace) {
+        let message = match trace.frame.to_rpcmesage() {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("Cannot record frame: {err}");
+                return;
+            }
+        };
+        let timestamp = trace.timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        let record = TrafficRecord {
+            timestamp,
+            direction: trace.direction,
+            message,
+        };
+        if let Some(record_tx) = &self.record_tx {
+            if record_tx.send(record).is_err() {
+                error!("Traffic recorder thread is not running, the record is lost");
+            }
+        }
+    }
+}
+
+/// A recorded session loaded for a replay
+pub struct TrafficRecording {
+    records: Vec<TrafficRecord>,
+}
+
+impl TrafficRecording {
+    pub fn read(reader: impl Read, format: RecordFormat) -> shvrpc::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut records = Vec::new();
+        match format {
+            RecordFormat::Cpon => {
+                for line in reader.lines() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let value = RpcValue::from_cpon(&line)
+                        .map_err(|err| format!("Cannot parse traffic record: {err}"))?;
+                    records.push(TrafficRecord::from_rpcvalue(value)?);
+                }
+            }
+            RecordFormat::ChainPack => {
+                let mut len_buf = [0u8; 4];
+                loop {
+                    match reader.read_exact(&mut len_buf) {
+                        Ok(()) => { }
+                        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                        Err(err) => return Err(err.into()),
+                    }
+                    let mut data = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+                    reader.read_exact(&mut data)?;
+                    let value = RpcValue::from_chainpack(&data)
+                        .map_err(|err| format!("Cannot parse traffic record: {err}"))?;
+                    records.push(TrafficRecord::from_rpcvalue(value)?);
+                }
+            }
+        }
+        Ok(Self { records })
+    }
+
+    pub fn open(path: impl AsRef<Path>, format: RecordFormat) -> shvrpc::Result<Self> {
+        Self::read(File::open(path)?, format)
+    }
+
+    pub fn records(&self) -> &[TrafficRecord] {
+        &self.records
+    }
+}
+
+pub struct ReplayOptions {
+    /// Keep the delays between the received frames as recorded
+    pub preserve_timing: bool,
+    /// How long to wait for the outgoing traffic to settle down
+    /// after the last frame has been injected
+    pub settle_time: Duration,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            preserve_timing: false,
+            settle_time: Duration::from_secs(1),
+        }
+    }
+}
+
+// Emulates the connection task. Injects the received side of the recording
+// and passes the messages sent by the client to the frame observer.
+pub(crate) async fn replay_task(
+    recording: TrafficRecording,
+    options: ReplayOptions,
+    conn_event_sender: Sender<ConnectionEvent>,
+    frame_observer: Option<FrameObserverRef>,
+) {
+    let (conn_cmd_sender, mut conn_cmd_receiver) = futures::channel::mpsc::unbounded();
+    if conn_event_sender.unbounded_send(ConnectionEvent::Connected(conn_cmd_sender)).is_err() {
+        return;
+    }
+
+    let observe_sent = |command: ConnectionCommand| {
+        let ConnectionCommand::SendMessage(message) = command;
+        if let (Some(observer), Ok(frame)) = (&frame_observer, message.to_frame()) {
+            observer.observe(&FrameTrace {
+                direction: FrameDirection::Sent,
+                timestamp: SystemTime::now(),
+                size: frame.data.len(),
+                frame: &frame,
+            });
+        }
+    };
+
+    let received: Vec<(i64, RpcFrame)> = recording.records
+        .into_iter()
+        .filter(|record| record.direction == FrameDirection::Received)
+        .filter_map(|record| match record.message.to_frame() {
+            Ok(frame) => Some((record.timestamp, frame)),
+            Err(err) => {
+                warn!("Skipping invalid recorded message: {err}");
+                None
+            }
+        })
+        .collect();
+
+    let mut last_timestamp = received.first().map(|(ts, _)| *ts);
+    for (timestamp, frame) in received {
+        if options.preserve_timing {
+            let delay = last_timestamp.map_or(0, |last| (timestamp - last).max(0) as u64);
+            last_timestamp = Some(timestamp);
+            let mut sleep = futures_time::task::sleep(Duration::from_millis(delay).into()).fuse();
+            loop {
+                select! {
+                    _ = sleep => break,
+                    command = conn_cmd_receiver.next().fuse() => match command {
+                        Some(command) => observe_sent(command),
+                        None => break,
+                    },
+                }
+            }
+        }
+        if let Some(observer) = &frame_observer {
+            observer.observe(&FrameTrace {
+                direction: FrameDirection::Received,
+                timestamp: SystemTime::now(),
+                size: frame.data.len(),
+                frame: &frame,
+            });
+        }
+        if conn_event_sender.unbounded_send(ConnectionEvent::RpcFrameReceived(frame)).is_err() {
+            return;
+        }
+    }
+
+    loop {
+        select! {
+            _ = futures_time::task::sleep(options.settle_time.into()).fuse() => break,
+            command = conn_cmd_receiver.next().fuse() => match command {
+                Some(command) => observe_sent(command),
+                None => break,
+            },
+        }
+    }
+    let _ = conn_event_sender.unbounded_send(ConnectionEvent::Disconnected);
+    // The client loop terminates when `conn_event_sender` is dropped here
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shvrpc::RpcMessageMetaTags;
+    use std::sync::{Arc, Mutex};
+
+    // In-memory output of a recorder, which can be read after the recorder is gone
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn contents(&self) -> Vec<u8> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn observe_message(recorder: &TrafficRecorder, direction: FrameDirection, message: &RpcMessage) {
+        let frame = message.to_frame().unwrap();
+        recorder.observe(&FrameTrace {
+            direction,
+            timestamp: SystemTime::now(),
+            size: frame.data.len(),
+            frame: &frame,
+        });
+    }
+
+    // Records a `get` request received by the client and its response
+    fn record_session(format: RecordFormat) -> (SharedBuffer, RpcMessage) {
+        let buffer = SharedBuffer::default();
+        let recorder = TrafficRecorder::new(buffer.clone(), format);
+        let request = RpcMessage::new_request("test/property", "get", None);
+        let mut response = request.prepare_response().unwrap();
+        response.set_result(42.into());
+        observe_message(&recorder, FrameDirection::Received, &request);
+        observe_message(&recorder, FrameDirection::Sent, &response);
+        (buffer, request)
+    }
+
+    fn check_recording(recording: &TrafficRecording, request: &RpcMessage) {
+        let records = recording.records();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].timestamp > 0);
+        assert!(records[0].timestamp <= records[1].timestamp);
+        assert_eq!(records[0].direction, FrameDirection::Received);
+        assert!(records[0].message.is_request());
+        assert_eq!(records[0].message.request_id(), request.request_id());
+        assert_eq!(records[0].message.shv_path(), Some("test/property"));
+        assert_eq!(records[0].message.method(), Some("get"));
+        assert_eq!(records[1].direction, FrameDirection::Sent);
+        assert!(records[1].message.is_response());
+        assert_eq!(records[1].message.request_id(), request.request_id());
+        assert_eq!(records[1].message.result().unwrap(), &RpcValue::from(42));
+    }
+
+    #[test]
+    fn cpon_round_trip() {
+        let (buffer, request) = record_session(RecordFormat::Cpon);
+        let recording = TrafficRecording::read(buffer.contents().as_slice(), RecordFormat::Cpon).unwrap();
+        check_recording(&recording, &request);
+    }
+
+    #[test]
+    fn chainpack_round_trip() {
+        let (buffer, request) = record_session(RecordFormat::ChainPack);
+        let recording = TrafficRecording::read(buffer.contents().as_slice(), RecordFormat::ChainPack).unwrap();
+        check_recording(&recording, &request);
+    }
+
+    #[test]
+    fn reject_invalid_record() {
+        assert!(TrafficRecording::read("[1, \"?\", {}]\n".as_bytes(), RecordFormat::Cpon).is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn replay_recorded_session() {
+        use crate::appnodes::DotAppNode;
+        use crate::propertynode::PropertyNode;
+        use crate::Client;
+
+        let (recorded, request) = record_session(RecordFormat::Cpon);
+        let recording = TrafficRecording::read(recorded.contents().as_slice(), RecordFormat::Cpon).unwrap();
+
+        let replayed = SharedBuffer::default();
+        let mut client = Client::<()>::new(DotAppNode::new("test"));
+        client
+            .mount_property("test/property", &PropertyNode::new(42))
+            .with_frame_observer(TrafficRecorder::new(replayed.clone(), RecordFormat::Cpon));
+        let options = ReplayOptions {
+            settle_time: Duration::from_millis(100),
+            ..Default::default()
+        };
+        ::tokio::runtime::Builder::new_multi_thread()
+            .build()
+            .unwrap()
+            .block_on(client.replay(recording, options))
+            .unwrap();
+        // The recorder writes out the records when it is dropped with the client
+        drop(client);
+
+        // The replayed client answers the recorded request the same way
+        let replayed = TrafficRecording::read(replayed.contents().as_slice(), RecordFormat::Cpon).unwrap();
+        check_recording(&replayed, &request);
+    }
+}
@@ -35,3 +35,25 @@ where
         _ => panic!("Could not find suitable async runtime"),
     };
 }
+
+/// Runs a blocking function on a thread of the current runtime dedicated
+/// to blocking operations. A panic of the function is resumed in the caller.
+pub(crate) async fn spawn_blocking<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    match current_task_runtime() {
+        #[cfg(feature = "tokio")]
+        Runtime::Tokio => match tokio::task::spawn_blocking(f).await {
+            Ok(result) => result,
+            Err(err) => match err.try_into_panic() {
+                Ok(panic) => std::panic::resume_unwind(panic),
+                Err(err) => panic!("Blocking task failed: {err}"),
+            },
+        },
+        #[cfg(feature = "async_std")]
+        Runtime::AsyncStd => async_std::task::spawn_blocking(f).await,
+        _ => panic!("Could not find suitable async runtime"),
+    }
+}
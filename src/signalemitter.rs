@@ -0,0 +1,70 @@
+use crate::client::ClientCommandSender;
+use log::warn;
+use shvrpc::RpcMessage;
+use shvproto::RpcValue;
+use std::sync::Mutex;
+
+// Sends the signals of a node handle once the node is mounted.
+// Signals emitted before the node is attached to a client are dropped.
+#[derive(Default)]
+pub(crate) struct SignalEmitter {
+    // mount path and the sender of the client
+    attachment: Mutex<Option<(String, ClientCommandSender)>>,
+}
+
+impl SignalEmitter {
+    pub(crate) fn attach(&self, path: &str, client_cmd_tx: ClientCommandSender) {
+        *self.attachment.lock().unwrap_or_else(|err| err.into_inner()) = Some((path.to_string(), client_cmd_tx));
+    }
+
+    pub(crate) fn emit(&self, signal: &str, value: RpcValue) {
+        let attachment = self.attachment.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some((path, client_cmd_tx)) = attachment.as_ref() {
+            let sig = RpcMessage::new_signal(path, signal, Some(value));
+            if let Err(err) = client_cmd_tx.send_message(sig) {
+                warn!("Cannot send `{signal}` signal of `{path}`: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use crate::client::{ClientCommand, ClientCommandSender, Receiver};
+    use shvrpc::RpcMessageMetaTags;
+    use shvproto::RpcValue;
+
+    // A sender to attach node handles to, with the receiving end of the client
+    pub(crate) fn command_channel() -> (ClientCommandSender, Receiver<ClientCommand>) {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        (ClientCommandSender { sender, response_guard: None }, receiver)
+    }
+
+    // Checks the next sent message is the signal and returns its param
+    pub(crate) fn expect_signal(receiver: &mut Receiver<ClientCommand>, path: &str, signal: &str) -> RpcValue {
+        let Ok(Some(ClientCommand::SendMessage { message })) = receiver.try_next() else {
+            panic!("Expected a message to be sent");
+        };
+        assert!(message.is_signal());
+        assert_eq!(message.shv_path(), Some(path));
+        assert_eq!(message.method(), Some(signal));
+        message.param().map_or_else(RpcValue::null, Clone::clone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::*;
+    use super::*;
+
+    #[test]
+    fn emit_after_attach() {
+        let emitter = SignalEmitter::default();
+        let (client_cmd_tx, mut receiver) = command_channel();
+        emitter.emit("chng", 1.into());
+        assert!(receiver.try_next().is_err());
+        emitter.attach("test/node", client_cmd_tx);
+        emitter.emit("chng", 2.into());
+        assert_eq!(expect_signal(&mut receiver, "test/node", "chng"), RpcValue::from(2));
+    }
+}
@@ -0,0 +1,116 @@
+//! Utilities for testing of client applications without a broker
+//!
+//! Run a `Client` by `Client::run_with_mock()` and drive the emulated
+//! broker connection from the init handler:
+//!
+//!```ignore
+//! client.run_with_mock(|connector, client_cmd_tx, client_evt_rx| {
+//!     tokio::spawn(async move {
+//!         let mut conn_mock = connector.connect();
+//!         let mut request = RpcMessage::new_request("status/delayed", "getDelayed", None);
+//!         request.set_access_level(AccessLevel::Browse);
+//!         conn_mock.emulate_receive_request(request);
+//!         let response = conn_mock.expect_send_message().await;
+//!         // ...
+//!     });
+//! }).await
+//!```
+//!
+//! The client loop terminates when the connector and all the connection
+//! mocks created by it are dropped.
+
+use crate::client::{Receiver, Sender};
+use crate::connection::{ConnectionCommand, ConnectionEvent};
+use futures::StreamExt;
+use log::error;
+use shvrpc::RpcMessage;
+use shvproto::RpcValue;
+
+pub struct MockConnector {
+    conn_evt_tx: Sender<ConnectionEvent>,
+}
+
+impl MockConnector {
+    pub(crate) fn new(conn_evt_tx: Sender<ConnectionEvent>) -> Self {
+        Self { conn_evt_tx }
+    }
+
+    /// Emulates a connection to a broker.
+    ///
+    /// The client gets disconnected when the returned mock is dropped.
+    pub fn connect(&self) -> ConnectionMock {
+        ConnectionMock::new(&self.conn_evt_tx)
+    }
+}
+
+pub struct ConnectionMock {
+    conn_evt_tx: Sender<ConnectionEvent>,
+    conn_cmd_rx: Receiver<ConnectionCommand>,
+}
+
+impl Drop for ConnectionMock {
+    fn drop(&mut self) {
+        if self.conn_evt_tx.unbounded_send(ConnectionEvent::Disconnected).is_err() {
+            error!("Disconnected event send error");
+        }
+    }
+}
+
+impl ConnectionMock {
+    fn new(conn_evt_tx: &Sender<ConnectionEvent>) -> Self {
+        let (conn_cmd_tx, conn_cmd_rx) = futures::channel::mpsc::unbounded::<ConnectionCommand>();
+        conn_evt_tx.unbounded_send(ConnectionEvent::Connected(conn_cmd_tx)).expect("Connected event send error");
+        Self {
+            conn_evt_tx: conn_evt_tx.clone(),
+            conn_cmd_rx,
+        }
+    }
+
+    pub fn emulate_receive_request(&self, request: RpcMessage) {
+        self.conn_evt_tx.unbounded_send(ConnectionEvent::RpcFrameReceived(request.to_frame().unwrap())).unwrap();
+    }
+
+    pub fn emulate_receive_response(&self, from_request: &RpcMessage, result: impl Into<RpcValue>) {
+        let mut resp = from_request.prepare_response().unwrap();
+        resp.set_result(result);
+        self.conn_evt_tx.unbounded_send(ConnectionEvent::RpcFrameReceived(resp.to_frame().unwrap())).unwrap();
+    }
+
+    pub fn emulate_receive_signal(&self, path: &str, sig_name: &str, param: Option<RpcValue>) {
+        let sig = RpcMessage::new_signal(path, sig_name, param);
+        self.conn_evt_tx.unbounded_send(ConnectionEvent::RpcFrameReceived(sig.to_frame().unwrap())).unwrap();
+    }
+
+    pub async fn expect_send_message(&mut self) -> RpcMessage {
+        let Some(ConnectionCommand::SendMessage(msg)) = self.conn_cmd_rx.next().await else {
+            panic!("ConnectionCommand receive error");
+        };
+        msg
+    }
+}
+
+// A temporary directory of a test, removed with its contents when dropped,
+// so also when the test fails
+#[cfg(test)]
+pub(crate) struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("shvclient-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).expect("Cannot create a temporary directory");
+        Self(path)
+    }
+
+    pub(crate) fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
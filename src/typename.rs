@@ -0,0 +1,305 @@
+// Names of Rust types in SHV method metadata
+
+const SHV_TYPE_NAME_CAPACITY: usize = 256;
+
+const INT_TYPES: [&[u8]; 6] = [b"i8", b"i16", b"i32", b"i64", b"i128", b"isize"];
+const UINT_TYPES: [&[u8]; 6] = [b"u8", b"u16", b"u32", b"u64", b"u128", b"usize"];
+const DOUBLE_TYPES: [&[u8]; 2] = [b"f32", b"f64"];
+const STRING_TYPES: [&[u8]; 3] = [b"String", b"str", b"char"];
+const LIST_TYPES: [&[u8]; 3] = [b"Vec", b"VecDeque", b"List"];
+const MAP_TYPES: [&[u8]; 3] = [b"Map", b"BTreeMap", b"HashMap"];
+// Types named by their first type argument
+const WRAPPER_TYPES: [&[u8]; 5] = [b"Box", b"Arc", b"Rc", b"Cow", b"Result"];
+
+/// Name of a type in method metadata, see `shv_type_name()`
+#[doc(hidden)]
+pub struct ShvTypeName {
+    buf: [u8; SHV_TYPE_NAME_CAPACITY],
+    len: usize,
+}
+
+impl ShvTypeName {
+    const fn new() -> Self {
+        Self { buf: [0; SHV_TYPE_NAME_CAPACITY], len: 0 }
+    }
+
+    const fn push(mut self, bytes: &[u8]) -> Self {
+        let mut i = 0;
+        while i < bytes.len() {
+            assert!(self.len < SHV_TYPE_NAME_CAPACITY, "Type name is too long");
+            self.buf[self.len] = bytes[i];
+            self.len += 1;
+            i += 1;
+        }
+        self
+    }
+
+    const fn as_bytes(&self) -> &[u8] {
+        self.buf.split_at(self.len).0
+    }
+
+    pub const fn as_str(&self) -> &str {
+        match std::str::from_utf8(self.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => panic!("Type name is not valid UTF-8"),
+        }
+    }
+}
+
+/// Converts a Rust type to its name in SHV method metadata, e.g.
+/// `Option<Vec<i32>>` to `[Int]|Null`. Parameter lists `{name: Type, ...}`
+/// are converted field by field. Other types are named by the last
+/// segment of their path without generic arguments.
+#[doc(hidden)]
+pub const fn shv_type_name(rust_type: &str) -> ShvTypeName {
+    map_type(rust_type.as_bytes(), 0).0
+}
+
+// Maps a type starting at `pos`, returns its name and the position after the type
+const fn map_type(s: &[u8], pos: usize) -> (ShvTypeName, usize) {
+    let pos = skip_spaces(s, pos);
+    if pos >= s.len() {
+        return (ShvTypeName::new(), pos);
+    }
+    match s[pos] {
+        b'&' => map_type(s, skip_reference(s, pos + 1)),
+        b'(' => map_tuple(s, pos + 1),
+        b'[' => map_slice(s, pos + 1),
+        b'{' => map_fields(s, pos + 1),
+        _ => map_path(s, pos),
+    }
+}
+
+const fn map_path(s: &[u8], pos: usize) -> (ShvTypeName, usize) {
+    let mut start = pos;
+    let mut end = ident_end(s, pos);
+    loop {
+        let next = skip_spaces(s, end);
+        if next + 1 < s.len() && s[next] == b':' && s[next + 1] == b':' {
+            start = skip_spaces(s, next + 2);
+            end = ident_end(s, start);
+        } else {
+            break;
+        }
+    }
+    let ident = subslice(s, start, end);
+    let mut pos = end;
+    let mut first_arg = ShvTypeName::new();
+    let mut first_arg_is_byte = false;
+    let mut arg_count = 0;
+    let next = skip_spaces(s, end);
+    if next < s.len() && s[next] == b'<' {
+        pos = skip_spaces(s, next + 1);
+        while pos < s.len() && s[pos] != b'>' {
+            let arg_end = if s[pos] == b'\'' {
+                // lifetime argument
+                ident_end(s, pos + 1)
+            } else {
+                let (arg, arg_end) = map_type(s, pos);
+                if arg_count == 0 {
+                    first_arg = arg;
+                    first_arg_is_byte = is_byte(s, pos, arg_end);
+                }
+                arg_count += 1;
+                arg_end
+            };
+            if arg_end == pos {
+                break;
+            }
+            pos = skip_separator(s, arg_end);
+        }
+        pos += 1;
+    }
+    let name = ShvTypeName::new();
+    let name = if is_one_of(ident, &INT_TYPES) {
+        name.push(b"Int")
+    } else if is_one_of(ident, &UINT_TYPES) {
+        name.push(b"UInt")
+    } else if is_one_of(ident, &DOUBLE_TYPES) {
+        name.push(b"Double")
+    } else if bytes_eq(ident, b"bool") {
+        name.push(b"Bool")
+    } else if is_one_of(ident, &STRING_TYPES) {
+        name.push(b"String")
+    } else if is_one_of(ident, &MAP_TYPES) {
+        name.push(b"Map")
+    } else if arg_count == 0 {
+        name.push(ident)
+    } else if bytes_eq(ident, b"Option") {
+        name.push(first_arg.as_bytes()).push(b"|Null")
+    } else if is_one_of(ident, &LIST_TYPES) {
+        list_name(first_arg, first_arg_is_byte)
+    } else if is_one_of(ident, &WRAPPER_TYPES) {
+        first_arg
+    } else {
+        name.push(ident)
+    };
+    (name, pos)
+}
+
+// `()` is Null, other tuples are lists
+const fn map_tuple(s: &[u8], pos: usize) -> (ShvTypeName, usize) {
+    let mut pos = skip_spaces(s, pos);
+    let name: &[u8] = if pos < s.len() && s[pos] == b')' { b"Null" } else { b"List" };
+    while pos < s.len() && s[pos] != b')' {
+        let end = map_type(s, pos).1;
+        if end == pos {
+            break;
+        }
+        pos = skip_separator(s, end);
+    }
+    (ShvTypeName::new().push(name), pos + 1)
+}
+
+// A slice `[T]` or an array `[T; N]`
+const fn map_slice(s: &[u8], pos: usize) -> (ShvTypeName, usize) {
+    let (item, end) = map_type(s, pos);
+    let name = list_name(item, is_byte(s, pos, end));
+    let mut pos = end;
+    while pos < s.len() && s[pos] != b']' {
+        pos += 1;
+    }
+    (name, pos + 1)
+}
+
+// Fields of several params, e.g. `{min: i32, max: i32}`
+const fn map_fields(s: &[u8], pos: usize) -> (ShvTypeName, usize) {
+    let mut name = ShvTypeName::new().push(b"{");
+    let mut pos = skip_spaces(s, pos);
+    while pos < s.len() && s[pos] != b'}' {
+        let field_end = ident_end(s, pos);
+        if field_end == pos {
+            break;
+        }
+        if name.len > 1 {
+            name = name.push(b", ");
+        }
+        name = name.push(subslice(s, pos, field_end)).push(b": ");
+        pos = skip_spaces(s, field_end);
+        if pos < s.len() && s[pos] == b':' {
+            pos += 1;
+        }
+        let (field_type, end) = map_type(s, pos);
+        name = name.push(field_type.as_bytes());
+        pos = skip_separator(s, end);
+    }
+    (name.push(b"}"), pos + 1)
+}
+
+const fn list_name(item: ShvTypeName, is_byte: bool) -> ShvTypeName {
+    if is_byte {
+        ShvTypeName::new().push(b"Blob")
+    } else {
+        ShvTypeName::new().push(b"[").push(item.as_bytes()).push(b"]")
+    }
+}
+
+// Checks whether the type between `start` and `end` is `u8`
+const fn is_byte(s: &[u8], start: usize, end: usize) -> bool {
+    let start = skip_spaces(s, start);
+    ident_end(s, start) == end && bytes_eq(subslice(s, start, end), b"u8")
+}
+
+// Skips the lifetime and `mut` of a reference
+const fn skip_reference(s: &[u8], pos: usize) -> usize {
+    let mut pos = skip_spaces(s, pos);
+    if pos < s.len() && s[pos] == b'\'' {
+        pos = skip_spaces(s, ident_end(s, pos + 1));
+    }
+    let end = ident_end(s, pos);
+    if bytes_eq(subslice(s, pos, end), b"mut") {
+        end
+    } else {
+        pos
+    }
+}
+
+// Skips a comma separating the arguments, if any
+const fn skip_separator(s: &[u8], pos: usize) -> usize {
+    let pos = skip_spaces(s, pos);
+    if pos < s.len() && s[pos] == b',' {
+        skip_spaces(s, pos + 1)
+    } else {
+        pos
+    }
+}
+
+const fn skip_spaces(s: &[u8], mut pos: usize) -> usize {
+    while pos < s.len() && s[pos].is_ascii_whitespace() {
+        pos += 1;
+    }
+    pos
+}
+
+const fn ident_end(s: &[u8], mut pos: usize) -> usize {
+    while pos < s.len() && (s[pos].is_ascii_alphanumeric() || s[pos] == b'_') {
+        pos += 1;
+    }
+    pos
+}
+
+const fn subslice(s: &[u8], start: usize, end: usize) -> &[u8] {
+    s.split_at(end).0.split_at(start).1
+}
+
+const fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn is_one_of(ident: &[u8], names: &[&[u8]]) -> bool {
+    let mut i = 0;
+    while i < names.len() {
+        if bytes_eq(ident, names[i]) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shv_type_names() {
+        const NAME: &str = shv_type_name(stringify!(Option<Vec<i32>>)).as_str();
+        assert_eq!(NAME, "[Int]|Null");
+        for (rust_type, shv_type) in [
+            ("i32", "Int"),
+            ("usize", "UInt"),
+            ("f64", "Double"),
+            ("bool", "Bool"),
+            ("String", "String"),
+            ("&'static str", "String"),
+            ("& mut String", "String"),
+            ("()", "Null"),
+            ("(i32, String)", "List"),
+            ("Option < i32 >", "Int|Null"),
+            ("Vec<u8>", "Blob"),
+            ("&[u8]", "Blob"),
+            ("[u16; 4]", "[UInt]"),
+            ("Vec<Vec<String>>", "[[String]]"),
+            ("BTreeMap<String, i32>", "Map"),
+            ("Result<bool, RpcError>", "Bool"),
+            ("Cow<'static, str>", "String"),
+            ("shvproto::DateTime", "DateTime"),
+            ("RpcValue", "RpcValue"),
+            ("Vec<Alert>", "[Alert]"),
+            ("MyTable<i32>", "MyTable"),
+            ("{min: i32, max: Option<i32>, labels: Vec<String>}", "{min: Int, max: Int|Null, labels: [String]}"),
+        ] {
+            assert_eq!(shv_type_name(rust_type).as_str(), shv_type, "{rust_type}");
+        }
+    }
+}
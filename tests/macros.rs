@@ -0,0 +1,171 @@
+use futures::StreamExt;
+use shvclient::appnodes::DotAppNode;
+use shvclient::clientnode::{AccessLevel, RpcError, RpcErrorCode, RpcMessage, RpcMessageMetaTags, RpcValue, SIG_CHNG};
+use shvclient::testing::ConnectionMock;
+use shvclient::{shv_node, Client, ClientEvent, NodeTree, ShvNode};
+use std::future::Future;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+// Runs the client with a mocked connection, `test` drives the broker side
+fn run_test<F, Fut>(mut client: Client<()>, test: F)
+where
+    F: FnOnce(ConnectionMock) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    ::tokio::runtime::Builder::new_multi_thread()
+        .build()
+        .unwrap()
+        .block_on(async move {
+            let (join_handle_tx, mut join_handle_rx) = futures::channel::mpsc::unbounded();
+            client
+                .run_with_mock(move |connector, _client_cmd_tx, mut client_evt_rx| {
+                    let join_handle = ::tokio::spawn(async move {
+                        let conn_mock = connector.connect();
+                        let ClientEvent::Connected = client_evt_rx.wait_for_event().await.expect("Client event receive") else {
+                            panic!("Expected Connected client event");
+                        };
+                        test(conn_mock).await;
+                    });
+                    join_handle_tx.unbounded_send(join_handle).unwrap();
+                })
+                .await
+                .expect("Client loop terminated with an error");
+            let join_handle = join_handle_rx.next().await.expect("fetch test join handle");
+            join_handle.await.expect("Test finished with error");
+        });
+}
+
+async fn request(conn_mock: &mut ConnectionMock, path: &str, method: &str, param: Option<RpcValue>) -> RpcMessage {
+    let mut request = RpcMessage::new_request(path, method, param);
+    request.set_access_level(AccessLevel::Service);
+    conn_mock.emulate_receive_request(request);
+    conn_mock.expect_send_message().await
+}
+
+async fn expect_chng(conn_mock: &mut ConnectionMock, path: &str) -> RpcValue {
+    let signal = conn_mock.expect_send_message().await;
+    assert!(signal.is_signal());
+    assert_eq!(signal.shv_path(), Some(path));
+    assert_eq!(signal.method(), Some(SIG_CHNG));
+    signal.param().map_or_else(RpcValue::null, Clone::clone)
+}
+
+#[derive(ShvNode)]
+struct Sensor {
+    #[shv(read_only)]
+    value: i32,
+}
+
+#[derive(ShvNode)]
+#[shv(methods)]
+struct Device {
+    name: String,
+    #[shv(rename = "sn", read_only)]
+    serial_number: String,
+    #[shv(subtree)]
+    sensor: Sensor,
+}
+
+#[shv_node]
+impl DeviceNodes {
+    #[method(access = Command, description = "Renames the device")]
+    async fn reset(&self, name: Option<String>) -> bool {
+        self.name.set(name.unwrap_or_else(|| "device".into()))
+    }
+}
+
+#[test]
+fn mount_derived_node_tree() {
+    let mut client = Client::new(DotAppNode::new("test"));
+    let device = Device {
+        name: "device".into(),
+        serial_number: "SN1".into(),
+        sensor: Sensor { value: 42 },
+    };
+    let nodes = device.mount_tree(&mut client, "device");
+    run_test(client, move |mut conn_mock| async move {
+        let response = request(&mut conn_mock, "device", "ls", None).await;
+        let children = response.result().unwrap().as_list().iter().map(RpcValue::as_str).collect::<Vec<_>>();
+        assert_eq!(children, ["name", "sensor", "sn"]);
+
+        // The metadata types are the SHV names of the field types
+        let response = request(&mut conn_mock, "device/name", "dir", Some(true.into())).await;
+        let methods = response.result().unwrap().as_list().iter().map(RpcValue::as_map).collect::<Vec<_>>();
+        let method = |name: &str| *methods.iter().find(|method| method.get("name").map(RpcValue::as_str) == Some(name)).unwrap();
+        assert_eq!(method("get").get("result").map(RpcValue::as_str), Some("String"));
+        assert_eq!(method("set").get("param").map(RpcValue::as_str), Some("String"));
+
+        let response = request(&mut conn_mock, "device/sensor/value", "get", None).await;
+        assert_eq!(response.result().unwrap(), &RpcValue::from(42));
+
+        let response = request(&mut conn_mock, "device/sn", "set", Some("SN2".into())).await;
+        assert!(response.result().is_err());
+
+        // The property emits `chng` before the response is sent
+        let mut set_name = RpcMessage::new_request("device/name", "set", Some("dev1".into()));
+        set_name.set_access_level(AccessLevel::Write);
+        conn_mock.emulate_receive_request(set_name);
+        assert_eq!(expect_chng(&mut conn_mock, "device/name").await, RpcValue::from("dev1"));
+        assert!(conn_mock.expect_send_message().await.result().is_ok());
+        assert_eq!(nodes.name.get(), "dev1");
+
+        // The root node methods are implemented on the nodes handles
+        let mut reset = RpcMessage::new_request("device", "reset", None);
+        reset.set_access_level(AccessLevel::Command);
+        conn_mock.emulate_receive_request(reset);
+        assert_eq!(expect_chng(&mut conn_mock, "device/name").await, RpcValue::from("device"));
+        assert_eq!(conn_mock.expect_send_message().await.result().unwrap(), &RpcValue::from(true));
+        assert_eq!(nodes.name.get(), "device");
+    });
+}
+
+struct Counter {
+    value: AtomicI32,
+}
+
+#[shv_node]
+impl Counter {
+    #[method(access = Read, getter, signals("chng"))]
+    fn get(&self) -> i32 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    #[method(access = Write, description = "Adds to the counter")]
+    async fn add(&self, delta: i32, limit: Option<i32>) -> Result<i32, RpcError> {
+        let value = self.value.fetch_add(delta, Ordering::Relaxed) + delta;
+        match limit {
+            Some(limit) if value > limit => Err(RpcError::new(RpcErrorCode::MethodCallException, "Over limit")),
+            _ => Ok(value),
+        }
+    }
+}
+
+#[test]
+fn mount_shv_node() {
+    let mut client = Client::new(DotAppNode::new("test"));
+    client.mount("counter", Counter { value: AtomicI32::new(1) }.into_node());
+    run_test(client, move |mut conn_mock| async move {
+        let response = request(&mut conn_mock, "counter", "get", None).await;
+        assert_eq!(response.result().unwrap(), &RpcValue::from(1));
+
+        let param = RpcValue::from_cpon(r#"{"delta": 2}"#).unwrap();
+        let response = request(&mut conn_mock, "counter", "add", Some(param)).await;
+        assert_eq!(response.result().unwrap(), &RpcValue::from(3));
+
+        let param = RpcValue::from_cpon("[2, 4]").unwrap();
+        let response = request(&mut conn_mock, "counter", "add", Some(param)).await;
+        assert_eq!(response.result().unwrap_err().code, RpcErrorCode::MethodCallException);
+
+        let response = request(&mut conn_mock, "counter", "add", Some("2".into())).await;
+        assert_eq!(response.result().unwrap_err().code, RpcErrorCode::InvalidParam);
+
+        let response = request(&mut conn_mock, "counter", "dir", Some(true.into())).await;
+        let methods = response.result().unwrap().as_list().iter().map(RpcValue::as_map).collect::<Vec<_>>();
+        let method = |name: &str| *methods.iter().find(|method| method.get("name").map(RpcValue::as_str) == Some(name)).unwrap();
+        assert_eq!(method("get").get("result").map(RpcValue::as_str), Some("Int"));
+        assert!(method("get").get("signals").unwrap().as_map().contains_key(SIG_CHNG));
+        assert_eq!(method("add").get("param").map(RpcValue::as_str), Some("{delta: Int, limit: Int|Null}"));
+        assert_eq!(method("add").get("result").map(RpcValue::as_str), Some("Int"));
+        assert_eq!(method("add").get("description").map(RpcValue::as_str), Some("Adds to the counter"));
+    });
+}
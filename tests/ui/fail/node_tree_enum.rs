@@ -0,0 +1,9 @@
+use shvclient::ShvNode;
+
+#[derive(ShvNode)]
+enum Mode {
+    On,
+    Off,
+}
+
+fn main() {}
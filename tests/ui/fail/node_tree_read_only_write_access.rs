@@ -0,0 +1,9 @@
+use shvclient::ShvNode;
+
+#[derive(ShvNode)]
+struct Device {
+    #[shv(read_only, write_access = Service)]
+    serial_number: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use shvclient::ShvNode;
+
+#[derive(ShvNode)]
+struct Device {
+    #[shv(hidden)]
+    serial_number: String,
+}
+
+fn main() {}
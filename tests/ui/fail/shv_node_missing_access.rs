@@ -0,0 +1,13 @@
+use shvclient::shv_node;
+
+struct Counter;
+
+#[shv_node]
+impl Counter {
+    #[method(getter)]
+    fn get(&self) -> i32 {
+        0
+    }
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use shvclient::shv_node;
+
+struct Counter;
+
+#[shv_node]
+impl Counter {
+    #[method(access = Write)]
+    fn reset(&mut self) {}
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use shvclient::shv_node;
+
+struct Counter;
+
+#[shv_node]
+impl Counter {
+    #[method(access = Browse, name = "ls")]
+    fn children(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+fn main() {}
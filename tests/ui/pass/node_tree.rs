@@ -0,0 +1,35 @@
+use shvclient::appnodes::DotAppNode;
+use shvclient::{shv_node, Client, NodeTree, ShvNode};
+
+#[derive(ShvNode)]
+struct Sensor {
+    #[shv(read_only, access = Browse, description = "Measured value", type = "Int")]
+    value: i32,
+}
+
+#[derive(ShvNode)]
+#[shv(methods)]
+struct Device {
+    #[shv(write_access = Service)]
+    name: String,
+    #[shv(subtree, rename = "mainSensor")]
+    sensor: Sensor,
+}
+
+#[shv_node]
+impl DeviceNodes {
+    #[method(access = Command)]
+    async fn reset(&self) -> bool {
+        self.name.set("reset".into())
+    }
+}
+
+fn main() {
+    let mut client = Client::<()>::new(DotAppNode::new("test"));
+    let device = Device {
+        name: "device".into(),
+        sensor: Sensor { value: 1 },
+    };
+    let nodes = device.mount_tree(&mut client, "device");
+    let _: i32 = nodes.sensor.value.get();
+}
@@ -0,0 +1,43 @@
+use shvclient::appnodes::DotAppNode;
+use shvclient::clientnode::{DeferredResponse, RpcError, RpcErrorCode, RpcMessage};
+use shvclient::{shv_node, Client, ClientCommandSender};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+struct Counter {
+    value: AtomicI32,
+}
+
+#[shv_node]
+impl Counter {
+    #[method(access = Read, getter, signals("chng"))]
+    fn get(&self) -> i32 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    #[method(access = Write, setter, name = "set")]
+    fn set_value(&self, value: i32) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    #[method(access = Write, description = "Adds to the counter")]
+    async fn add(&self, delta: i32, limit: Option<i32>) -> Result<i32, RpcError> {
+        let value = self.value.fetch_add(delta, Ordering::Relaxed) + delta;
+        match limit {
+            Some(limit) if value > limit => Err(RpcError::new(RpcErrorCode::MethodCallException, "Over limit")),
+            _ => Ok(value),
+        }
+    }
+
+    #[method(access = Command, param = "Int", result = "Null")]
+    fn reset_later(&self, request: RpcMessage, client_cmd_tx: ClientCommandSender, _delay: Option<u64>) -> DeferredResponse {
+        self.value.store(0, Ordering::Relaxed);
+        let resp = request.prepare_response().unwrap_or_default();
+        let _ = client_cmd_tx.send_message(resp);
+        DeferredResponse
+    }
+}
+
+fn main() {
+    let mut client = Client::<()>::new(DotAppNode::new("test"));
+    client.mount("counter", Counter { value: AtomicI32::new(0) }.into_node());
+}